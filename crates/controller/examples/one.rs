@@ -2,8 +2,8 @@ use bevy::{
     prelude::{
         shape, App, Assets, BuildChildren, Camera3dBundle, ClearColor, Color, Commands, Component,
         EventReader, GlobalTransform, Input, KeyCode, Mat4, Mesh, Msaa, PbrBundle, PointLight,
-        PointLightBundle, PreUpdate, Quat, Query, Res, ResMut, Resource, SpotLight,
-        SpotLightBundle, StandardMaterial, Startup, Transform, Update, Vec3, Visibility, With,
+        PointLightBundle, PreUpdate, Quat, Query, Res, ResMut, StandardMaterial, Startup, Transform,
+        Update, Vec3, With,
         Without,
     },
     DefaultPlugins,
@@ -2,16 +2,15 @@ use bevy::{
     prelude::{
         shape, App, Assets, BuildChildren, Camera3dBundle, ClearColor, Color, Commands,
         ComputedVisibility, GlobalTransform, Mat4, Mesh, Msaa, PbrBundle, PointLightBundle, Quat,
-        Res, ResMut, SpatialBundle, StandardMaterial, Startup, Transform, Update, Vec3, Visibility,
+        Res, ResMut, StandardMaterial, Startup, Transform, Update, Vec3, Visibility,
     },
     transform::TransformBundle,
-    utils::petgraph::visit::Visitable,
     DefaultPlugins,
 };
 use bevy_rapier3d::{
     prelude::{
-        Collider, ColliderMassProperties, LockedAxes, NoUserData, RapierConfiguration,
-        RapierPhysicsPlugin, RigidBody, Sleeping, TimestepMode,
+        Collider, ColliderMassProperties, LockedAxes, NoUserData, RapierPhysicsPlugin, RigidBody,
+        Sleeping,
     },
     render::RapierDebugRenderPlugin,
 };
@@ -21,9 +20,7 @@ use controller::{
         YawTag,
     },
     look::{LookDirection, LookEntity},
-    rapier::{
-        RapierDynamicForceCharacterControllerPlugin, RapierDynamicImpulseCharacterControllerPlugin,
-    },
+    rapier::RapierDynamicImpulseCharacterControllerPlugin,
     utils::CharacterSettings,
 };
 use rand::Rng;
@@ -137,9 +134,6 @@ pub fn spawn_character(
     let cube = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
     let red = materials.add(Color::hex("800000").unwrap().into());
 
-    let body_tmf = 0.5 * (box_y + character_settings.scale.y) * Vec3::Y;
-
-
     let body_tmf = Vec3::new(0., 10., 0.);
     let body = commands
         .spawn((
@@ -158,8 +152,8 @@ pub fn spawn_character(
         .insert(LockedAxes::ROTATION_LOCKED)
         .insert(ColliderMassProperties::Density(200.0))
         .insert(Collider::capsule(
-            (-0.5 * character_settings.scale.y * Vec3::Y).into(),
-            (0.5 * character_settings.scale.y * Vec3::Y).into(),
+            -0.5 * character_settings.scale.y * Vec3::Y,
+            0.5 * character_settings.scale.y * Vec3::Y,
             0.5 * character_settings.scale.x.max(character_settings.scale.z),
         ))
         // .insert(RigidBodyPositionSync::Interpolated { prev_pos: None })
@@ -30,9 +30,9 @@ pub struct CameraTag;
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum ControllerSet {
-    INPUT_TO_EVENT,
-    INPUT_TO_LOOK,
-    FORWARD_UP,
+    InputToEvent,
+    InputToLook,
+    ForwardUp,
 }
 
 pub struct CharacterControllerPlugin;
@@ -54,9 +54,9 @@ impl Plugin for CharacterControllerPlugin {
                 PreUpdate,
                 // chain() will ensure sets run in the order they are listed
                 (
-                    ControllerSet::INPUT_TO_EVENT,
-                    ControllerSet::INPUT_TO_LOOK,
-                    ControllerSet::FORWARD_UP,
+                    ControllerSet::InputToEvent,
+                    ControllerSet::InputToLook,
+                    ControllerSet::ForwardUp,
                 )
                     .chain(),
             )
@@ -64,14 +64,14 @@ impl Plugin for CharacterControllerPlugin {
                 PreUpdate,
                 (
                     cursor_grab,
-                    (input_to_events).in_set(ControllerSet::INPUT_TO_EVENT),
+                    (input_to_events).in_set(ControllerSet::InputToEvent),
                     (input_to_look)
-                        .in_set(ControllerSet::INPUT_TO_LOOK)
-                        .after(ControllerSet::INPUT_TO_EVENT),
+                        .in_set(ControllerSet::InputToLook)
+                        .after(ControllerSet::InputToEvent),
                     (forward_up)
-                        .in_set(ControllerSet::FORWARD_UP)
-                        .after(ControllerSet::INPUT_TO_EVENT)
-                        .after(ControllerSet::INPUT_TO_LOOK),
+                        .in_set(ControllerSet::ForwardUp)
+                        .after(ControllerSet::InputToEvent)
+                        .after(ControllerSet::InputToLook),
                 ),
             );
     }
@@ -147,7 +147,7 @@ pub fn input_to_events(
     controller_flag: Res<ControllerFlag>,
 ) {
     let xz = Vec3::new(1.0, 0.0, 1.0);
-    if (!controller_flag.flag) {
+    if !controller_flag.flag {
         return;
     }
     for (mass, look_entity, mut controller) in controller_query.iter_mut() {
@@ -328,7 +328,7 @@ fn toggle_grab_cursor(window: &mut Window) {
 // 光标显示或者隐藏系统
 fn cursor_grab(
     keys: Res<Input<KeyCode>>,
-    controller_query: Query<(&CharacterController)>,
+    controller_query: Query<&CharacterController>,
     mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
 ) {
     let controller = controller_query.single();
@@ -5,13 +5,8 @@ use bevy::prelude::{
     Plugin, PreUpdate, Query, Res, ResMut, SystemSet, Update, Vec3, With, Without,
 };
 use bevy_rapier3d::{
-    prelude::{
-        Collider, ColliderMassProperties, CollisionGroups, RapierColliderHandle,
-        RapierConfiguration, RapierContext, RapierRigidBodyHandle, RigidBody, TimestepMode,
-    },
-    rapier::prelude::{
-        ColliderFlags, InteractionGroups, RigidBodyMassProps, RigidBodySet, RigidBodyVelocity,
-    },
+    prelude::{CollisionGroups, RapierConfiguration, RapierContext, RapierRigidBodyHandle},
+    rapier::prelude::RigidBodyMassProps,
 };
 
 use crate::{
@@ -24,11 +19,11 @@ use crate::{
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
 enum RapiserSet {
-    BODY_TO_VELOCITY_SYSTEM,
-    CONTROLLER_TO_RAPIER_DYNAMIC_IMPULSE_SYSTEM,
-    CONTROLLER_TO_RAPIER_DYNAMIC_FORCE_SYSTEM,
-    CREATE_MASS_FROM_RAPIER_SYSTEM,
-    TOGGLE_FLY_MODE_SYSTEM,
+    BodyToVelocity,
+    ControllerToRapierDynamicImpulse,
+    ControllerToRapierDynamicForce,
+    CreateMassFromRapier,
+    ToggleFlyMode,
 }
 
 pub struct RapierDynamicImpulseCharacterControllerPlugin;
@@ -36,31 +31,31 @@ pub struct RapierDynamicImpulseCharacterControllerPlugin;
 impl Plugin for RapierDynamicImpulseCharacterControllerPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(CharacterControllerPlugin)
-            .configure_set(PreUpdate, RapiserSet::TOGGLE_FLY_MODE_SYSTEM)
+            .configure_set(PreUpdate, RapiserSet::ToggleFlyMode)
             .configure_sets(
                 Update,
                 (
-                    RapiserSet::CREATE_MASS_FROM_RAPIER_SYSTEM,
-                    RapiserSet::BODY_TO_VELOCITY_SYSTEM,
-                    RapiserSet::CONTROLLER_TO_RAPIER_DYNAMIC_IMPULSE_SYSTEM,
+                    RapiserSet::CreateMassFromRapier,
+                    RapiserSet::BodyToVelocity,
+                    RapiserSet::ControllerToRapierDynamicImpulse,
                 )
                     .chain(),
             )
             .add_systems(
                 PreUpdate,
                 toggle_fly_mode
-                    .in_set(RapiserSet::TOGGLE_FLY_MODE_SYSTEM)
-                    .after(ControllerSet::INPUT_TO_EVENT),
+                    .in_set(RapiserSet::ToggleFlyMode)
+                    .after(ControllerSet::InputToEvent),
             )
             .add_systems(
                 Update,
                 (
-                    (create_mass_from_rapier).in_set(RapiserSet::CREATE_MASS_FROM_RAPIER_SYSTEM),
-                    (body_to_velocity).in_set(RapiserSet::BODY_TO_VELOCITY_SYSTEM),
+                    (create_mass_from_rapier).in_set(RapiserSet::CreateMassFromRapier),
+                    (body_to_velocity).in_set(RapiserSet::BodyToVelocity),
                     (controller_to_rapier_dynamic_impulse)
-                        .in_set(RapiserSet::CONTROLLER_TO_RAPIER_DYNAMIC_IMPULSE_SYSTEM)
-                        .after(RapiserSet::BODY_TO_VELOCITY_SYSTEM)
-                        .after(RapiserSet::CREATE_MASS_FROM_RAPIER_SYSTEM),
+                        .in_set(RapiserSet::ControllerToRapierDynamicImpulse)
+                        .after(RapiserSet::BodyToVelocity)
+                        .after(RapiserSet::CreateMassFromRapier),
                     (controller_to_yaw, controller_to_pitch),
                 ),
             );
@@ -73,31 +68,31 @@ pub struct RapierDynamicForceCharacterControllerPlugin;
 impl Plugin for RapierDynamicForceCharacterControllerPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(CharacterControllerPlugin)
-            .configure_set(PreUpdate, RapiserSet::TOGGLE_FLY_MODE_SYSTEM)
+            .configure_set(PreUpdate, RapiserSet::ToggleFlyMode)
             .configure_sets(
                 Update,
                 (
-                    RapiserSet::CREATE_MASS_FROM_RAPIER_SYSTEM,
-                    RapiserSet::BODY_TO_VELOCITY_SYSTEM,
-                    RapiserSet::CONTROLLER_TO_RAPIER_DYNAMIC_FORCE_SYSTEM,
+                    RapiserSet::CreateMassFromRapier,
+                    RapiserSet::BodyToVelocity,
+                    RapiserSet::ControllerToRapierDynamicForce,
                 )
                     .chain(),
             )
             .add_systems(
                 PreUpdate,
                 toggle_fly_mode
-                    .in_set(RapiserSet::TOGGLE_FLY_MODE_SYSTEM)
-                    .after(ControllerSet::INPUT_TO_EVENT),
+                    .in_set(RapiserSet::ToggleFlyMode)
+                    .after(ControllerSet::InputToEvent),
             )
             .add_systems(
                 Update,
                 (
-                    (create_mass_from_rapier).in_set(RapiserSet::CREATE_MASS_FROM_RAPIER_SYSTEM),
-                    (body_to_velocity).in_set(RapiserSet::BODY_TO_VELOCITY_SYSTEM),
+                    (create_mass_from_rapier).in_set(RapiserSet::CreateMassFromRapier),
+                    (body_to_velocity).in_set(RapiserSet::BodyToVelocity),
                     (controller_to_rapier_dynamic_force)
-                        .in_set(RapiserSet::CONTROLLER_TO_RAPIER_DYNAMIC_FORCE_SYSTEM)
-                        .after(RapiserSet::BODY_TO_VELOCITY_SYSTEM)
-                        .after(RapiserSet::CREATE_MASS_FROM_RAPIER_SYSTEM),
+                        .in_set(RapiserSet::ControllerToRapierDynamicForce)
+                        .after(RapiserSet::BodyToVelocity)
+                        .after(RapiserSet::CreateMassFromRapier),
                     (controller_to_yaw, controller_to_pitch),
                 ),
             );
@@ -107,7 +102,7 @@ impl Plugin for RapierDynamicForceCharacterControllerPlugin {
 pub fn controller_to_rapier_dynamic_impulse(
     mut impulses: EventReader<ImpulseEvent>,
     mut context: ResMut<RapierContext>,
-    mut query: Query<(&RapierRigidBodyHandle), With<BodyTag>>,
+    mut query: Query<&RapierRigidBodyHandle, With<BodyTag>>,
 ) {
     let mut impulse = Vec3::ZERO;
     for event in impulses.iter() {
@@ -132,7 +127,7 @@ pub fn controller_to_rapier_dynamic_impulse(
 pub fn controller_to_rapier_dynamic_force(
     mut forces: EventReader<ForceEvent>,
     mut context: ResMut<RapierContext>,
-    mut query: Query<(&RapierRigidBodyHandle), With<BodyTag>>,
+    mut query: Query<&RapierRigidBodyHandle, With<BodyTag>>,
 ) {
     let mut force = Vec3::ZERO;
     for event in forces.iter() {
@@ -142,11 +137,8 @@ pub fn controller_to_rapier_dynamic_force(
     if force.length_squared() > 1E-6 {
         for ele in query.iter_mut() {
             let body = context.bodies.get_mut(ele.0);
-            match body {
-                Some(b) => {
-                    b.add_force(force.into(), true);
-                }
-                None => {}
+            if let Some(b) = body {
+                b.add_force(force.into(), true);
             }
         }
     }
@@ -163,12 +155,12 @@ pub fn body_to_velocity(
         let b = body.unwrap();
         controller.velocity = (*b.linvel()).into();
         let speed = controller.velocity.distance_squared(Vec3::ZERO);
-        if (speed >= 300.) {
-            if (!b.is_ccd_enabled()) {
+        if speed >= 300. {
+            if !b.is_ccd_enabled() {
                 b.enable_ccd(true);
             }
         } else {
-            if (b.is_ccd_enabled()) {
+            if b.is_ccd_enabled() {
                 b.enable_ccd(false);
             }
         }
@@ -183,13 +175,10 @@ pub fn create_mass_from_rapier(
     let context = &mut *context;
     for (entity, handle) in query.iter() {
         let body = context.bodies.get(handle.0);
-        match body {
-            Some(b) => {
-                let mass_props: &RigidBodyMassProps = b.mass_properties();
-                let effective_mass = mass_props.effective_mass();
-                commands.entity(entity).insert(Mass::new(effective_mass.x));
-            }
-            None => {}
+        if let Some(b) = body {
+            let mass_props: &RigidBodyMassProps = b.mass_properties();
+            let effective_mass = mass_props.effective_mass();
+            commands.entity(entity).insert(Mass::new(effective_mass.x));
         }
     }
 }
@@ -202,7 +191,7 @@ fn toggle_fly_mode(
     mut rapier_config: ResMut<RapierConfiguration>,
     mut query: Query<(&CharacterController, &mut CollisionGroups)>,
 ) {
-    for (controller, mut collider) in query.iter_mut() {
+    for (controller, _collider) in query.iter_mut() {
         if keyboard_input.just_pressed(controller.input_map.key_fly) {
             rapier_config.gravity = if controller.fly {
                 // collider_flags.collision_groups = InteractionGroups::none();
@@ -1,31 +1,73 @@
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, HashSet},
     hash::{Hash, Hasher},
 };
 
 use bevy::prelude::{IVec3, Resource, Vec3};
 
-use crate::{clip_spheres::Sphere3, CHUNK_SIZE};
+use crate::{clip_spheres::Sphere3, coordinate::WorldPos, CHUNK_SIZE};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ChunkKey(pub IVec3);
 
 impl ChunkKey {
-    pub fn as_u8_array(&self) -> [u8; 8] {
+    // 数据库中使用的 key: 三个轴按 x y z 顺序 大端序并且符号位取反
+    // 这样字节序和坐标大小一致 不会冲突也可以做范围扫描
+    pub fn as_u8_array(&self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[0..4].copy_from_slice(&encode_axis(self.0.x));
+        bytes[4..8].copy_from_slice(&encode_axis(self.0.y));
+        bytes[8..12].copy_from_slice(&encode_axis(self.0.z));
+        bytes
+    }
+
+    pub fn from_u8_array(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 12 {
+            return None;
+        }
+        Some(ChunkKey(IVec3::new(
+            decode_axis(&bytes[0..4]),
+            decode_axis(&bytes[4..8]),
+            decode_axis(&bytes[8..12]),
+        )))
+    }
+
+    // 老版本使用的 hash key 只在读取旧的世界时用来迁移数据
+    pub fn as_legacy_u8_array(&self) -> [u8; 8] {
         let mut hasher = DefaultHasher::new();
         self.0.hash(&mut hasher);
-        let hash_value = hasher.finish();
-        let hash_bytes: [u8; 8] = unsafe { std::mem::transmute(hash_value) };
-        return hash_bytes;
+        hasher.finish().to_ne_bytes()
     }
 }
 
-// 生成 y 偏移两为0的移动
-pub fn generate_offset_array_with_y_0(chunk_distance: i32) -> Vec<IVec3> {
+fn encode_axis(v: i32) -> [u8; 4] {
+    ((v as u32) ^ 0x8000_0000).to_be_bytes()
+}
+
+fn decode_axis(bytes: &[u8]) -> i32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(bytes);
+    (u32::from_be_bytes(buf) ^ 0x8000_0000) as i32
+}
+
+// 偏移是否在圆柱形的范围内 水平按圆形 垂直按高度 单位都是 chunk
+pub fn in_cylinder(offset: IVec3, chunk_radius: f32, chunk_height: i32) -> bool {
+    offset.y.abs() <= chunk_height
+        && ((offset.x * offset.x + offset.z * offset.z) as f32) <= chunk_radius * chunk_radius
+}
+
+// 生成圆柱形范围内的偏移 每个 chunk 都是独立的 16³ 区块
+pub fn generate_offset_array(chunk_radius: f32, chunk_height: i32) -> Vec<IVec3> {
+    let distance = chunk_radius as i32;
     let mut offsets = Vec::new();
-    for x in -chunk_distance as i32..=chunk_distance as i32 {
-        for z in -chunk_distance as i32..=chunk_distance as i32 {
-            offsets.push(IVec3::new(x, 0, z));
+    for x in -distance..=distance {
+        for y in -chunk_height..=chunk_height {
+            for z in -distance..=distance {
+                let offset = IVec3::new(x, y, z);
+                if in_cylinder(offset, chunk_radius, chunk_height) {
+                    offsets.push(offset);
+                }
+            }
         }
     }
 
@@ -35,72 +77,116 @@ pub fn generate_offset_array_with_y_0(chunk_distance: i32) -> Vec<IVec3> {
 #[derive(Debug, Resource, Clone)]
 pub struct NeighbourOffest(pub Vec<IVec3>);
 
-pub fn generate_offset_resoure(radius: f32) -> NeighbourOffest {
-    let chunk_distance = radius as i32 / CHUNK_SIZE;
-    let mut offsets = generate_offset_array_with_y_0(chunk_distance);
-    // itself
-    offsets.push(IVec3::ZERO);
+// radius 是水平的半径 height 是上下的距离 单位都是方块
+pub fn generate_offset_resoure(radius: f32, height: f32) -> NeighbourOffest {
+    NeighbourOffest(generate_offset_array(
+        radius / CHUNK_SIZE as f32,
+        height as i32 / CHUNK_SIZE,
+    ))
+}
+
+// 六个方向的邻居
+pub const NEIGHBOUR_SIDES: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
 
-    NeighbourOffest(offsets)
+// 在偏移量外面再加一圈 生成 mesh 时需要六个方向的数据
+pub fn offsets_with_neighbour_ring(offsets: &[IVec3]) -> Vec<IVec3> {
+    let mut set = HashSet::new();
+    for &ele in offsets.iter() {
+        set.insert(ele);
+        for side in NEIGHBOUR_SIDES {
+            set.insert(ele + side);
+        }
+    }
+    set.into_iter().collect()
 }
 
-pub fn find_chunk_keys_array_by_shpere_y_0(sphere: Sphere3, offsets: Vec<IVec3>) -> Vec<ChunkKey> {
-    let mut center_chunk_point = get_chunk_key_i3_by_vec3(sphere.center);
-    center_chunk_point.y = 0;
+pub fn find_chunk_keys_array_by_shpere(sphere: Sphere3, offsets: Vec<IVec3>) -> Vec<ChunkKey> {
+    let center_chunk_point = WorldPos(sphere.center).chunk_key().0;
     offsets
         .iter()
         .map(|&ele| ChunkKey(center_chunk_point + ele))
         .collect()
 }
 
-// offsets 已经改变成了平面为零的情况 数据需要扩展的是y轴
-pub fn find_chunk_keys_by_shpere_to_full_height(
-    sphere: Sphere3,
-    offsets: Vec<IVec3>,
-    mut rt: impl FnMut(ChunkKey),
-) {
-    let mut center_chunk_point = get_chunk_key_i3_by_vec3(sphere.center);
-    center_chunk_point.y = 0;
-    for &ele in offsets.iter() {
-        for y_offset in -7..=8 {
-            rt(ChunkKey(
-                center_chunk_point
-                    + ele
-                    + IVec3 {
-                        x: 0,
-                        y: y_offset,
-                        z: 0,
-                    },
-            ))
+// 视线方向的偏向程度 0 时只按距离排序
+pub const VIEW_DIRECTION_BIAS: f32 = 0.3;
+
+// 按照 chunk 中心和相机的距离排序 近的先加载
+// 有视线方向时 前方的 chunk 优先 紧挨着相机的不受影响
+pub fn sort_by_load_priority(keys: &mut Vec<ChunkKey>, center: Vec3, forward: Option<Vec3>) {
+    let priority = |key: &ChunkKey| -> f32 {
+        let chunk_center = key.center().0;
+        let offset = chunk_center - center;
+        let distance = offset.length() / CHUNK_SIZE as f32;
+        match forward {
+            Some(dir) if distance > 1.5 => {
+                let facing = offset.normalize_or_zero().dot(dir.normalize_or_zero());
+                distance * (1.0 - VIEW_DIRECTION_BIAS * facing)
+            }
+            _ => distance,
         }
-    }
+    };
+    let mut with_priority: Vec<(f32, ChunkKey)> =
+        keys.drain(..).map(|key| (priority(&key), key)).collect();
+    with_priority.sort_by(|a, b| a.0.total_cmp(&b.0));
+    keys.extend(with_priority.into_iter().map(|(_, key)| key));
 }
 
-// 新版获取当前的 chunk_Key
-pub fn get_chunk_key_i3_by_vec3(point: Vec3) -> IVec3 {
-    IVec3 {
-        x: get_chunk_key_axis(point.x),
-        y: get_chunk_key_axis(point.y),
-        z: get_chunk_key_axis(point.z),
+#[test]
+fn test_chunk_key_bytes() {
+    let keys = vec![
+        ChunkKey(IVec3::new(i32::MIN, 0, 0)),
+        ChunkKey(IVec3::new(-2, 5, -1)),
+        ChunkKey(IVec3::new(-1, -7, 3)),
+        ChunkKey(IVec3::new(-1, 0, 3)),
+        ChunkKey(IVec3::new(0, 0, 0)),
+        ChunkKey(IVec3::new(1, -1, 0)),
+        ChunkKey(IVec3::new(i32::MAX, 8, -3)),
+    ];
+    for pair in keys.windows(2) {
+        assert!(pair[0].as_u8_array() < pair[1].as_u8_array());
+    }
+    for key in keys {
+        assert_eq!(ChunkKey::from_u8_array(&key.as_u8_array()), Some(key));
     }
+    assert_eq!(ChunkKey::from_u8_array(&[0u8; 8]), None);
 }
 
-fn get_chunk_key_axis(x: f32) -> i32 {
-    let x_dot = x * 10.;
-    if x > 8. {
-        return ((x_dot - (CHUNK_SIZE as f32 / 2.0) * 10.) as i32 / (CHUNK_SIZE * 10)) + 1;
-    }
-    if x < -8. {
-        return ((x_dot + (CHUNK_SIZE as f32 / 2.0) * 10.) as i32 / (CHUNK_SIZE * 10)) - 1;
-    }
-    0
+#[test]
+fn test_generate_offset_array() {
+    let offsets = generate_offset_array(8.0, 2);
+    assert!(offsets.contains(&IVec3::ZERO));
+    assert!(offsets.contains(&IVec3::new(8, 2, 0)));
+    assert!(!offsets.contains(&IVec3::new(8, 0, 8)));
+    assert!(!offsets.contains(&IVec3::new(0, 3, 0)));
+    // 去掉角落后 大约是正方形的 π/4
+    let square = 17 * 17 * 5;
+    assert!(offsets.len() < square * 82 / 100);
+    assert!(offsets.iter().all(|o| in_cylinder(*o, 8.0, 2)));
 }
 
 #[test]
-fn test_chunk_key_axis() {
-    let res = get_chunk_key_axis(24.5);
-    println!("{}", res);
+fn test_sort_by_load_priority() {
+    let mut keys = vec![
+        ChunkKey(IVec3::new(5, 0, 0)),
+        ChunkKey(IVec3::new(-3, 0, 0)),
+        ChunkKey(IVec3::new(0, 0, 1)),
+        ChunkKey(IVec3::new(3, 0, 0)),
+    ];
+    sort_by_load_priority(&mut keys, Vec3::ZERO, None);
+    assert_eq!(keys[0], ChunkKey(IVec3::new(0, 0, 1)));
+    assert_eq!(keys[3], ChunkKey(IVec3::new(5, 0, 0)));
 
-    let res2 = get_chunk_key_i3_by_vec3(Vec3::new(2.5, 24.5, 0.5));
-    println!("{}", res2);
+    // 看向 -x 时同样距离的 -3 先于 3
+    sort_by_load_priority(&mut keys, Vec3::ZERO, Some(Vec3::NEG_X));
+    assert_eq!(keys[0], ChunkKey(IVec3::new(0, 0, 1)));
+    assert_eq!(keys[1], ChunkKey(IVec3::new(-3, 0, 0)));
+    assert_eq!(keys[2], ChunkKey(IVec3::new(3, 0, 0)));
 }
@@ -0,0 +1,298 @@
+// chunk 数据在磁盘上的编码
+// 头部: [CHUNK_MAGIC, 版本, 编码方式] 后面是对应编码的数据
+// 老版本直接保存 bincode 的 Vec<Voxel> 第一个字节是长度的低位(0) 不会和 CHUNK_MAGIC 冲突
+use std::fmt;
+
+use ndshape::{ConstShape, ConstShape3u32};
+
+use crate::{voxel::Voxel, CHUNK_SIZE_U32};
+
+pub const CHUNK_MAGIC: u8 = 0xCE;
+pub const CHUNK_CODEC_VERSION: u8 = 1;
+
+type SampleShape = ConstShape3u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
+const CHUNK_LEN: usize = SampleShape::SIZE as usize;
+
+// 全部是同一种体素 [id]
+const ENCODING_UNIFORM: u8 = 0;
+// 游程编码 [(长度 u16 小端, id)...]
+const ENCODING_RLE: u8 = 1;
+// 调色板 + 位压缩 [调色板长度, id..., 索引位数据]
+const ENCODING_PACKED: u8 = 2;
+// 只保存和生成地形不同的体素 [个数 u16 小端, (索引 u16 小端, id)...]
+const ENCODING_DELTA: u8 = 3;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Truncated,
+    UnknownVersion(u8),
+    UnknownEncoding(u8),
+    BadLength(usize),
+    BadPalette(u8),
+    BadIndex(u16),
+    Legacy(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "chunk data truncated"),
+            DecodeError::UnknownVersion(v) => write!(f, "unknown chunk version {}", v),
+            DecodeError::UnknownEncoding(e) => write!(f, "unknown chunk encoding {}", e),
+            DecodeError::BadLength(len) => write!(f, "chunk has {} voxels", len),
+            DecodeError::BadPalette(i) => write!(f, "palette index {} out of range", i),
+            DecodeError::BadIndex(i) => write!(f, "voxel index {} out of range", i),
+            DecodeError::Legacy(e) => write!(f, "legacy chunk: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn encode_chunk(voxels: &[Voxel]) -> Vec<u8> {
+    let palette = build_palette(voxels);
+    let mut data = vec![CHUNK_MAGIC, CHUNK_CODEC_VERSION];
+    if palette.len() == 1 {
+        data.push(ENCODING_UNIFORM);
+        data.push(palette[0]);
+        return data;
+    }
+    let rle = encode_rle(voxels);
+    let packed = encode_packed(voxels, &palette);
+    if rle.len() <= packed.len() {
+        data.push(ENCODING_RLE);
+        data.extend(rle);
+    } else {
+        data.push(ENCODING_PACKED);
+        data.extend(packed);
+    }
+    data
+}
+
+// 和生成地形比较 得到修改过的体素
+pub fn diff_chunk(base: &[Voxel], voxels: &[Voxel]) -> Vec<(u16, Voxel)> {
+    base.iter()
+        .zip(voxels.iter())
+        .enumerate()
+        .filter(|(_, (a, b))| a.id != b.id)
+        .map(|(i, (_, b))| (i as u16, *b))
+        .collect()
+}
+
+pub fn encode_delta(overrides: &[(u16, Voxel)]) -> Vec<u8> {
+    let mut data = vec![CHUNK_MAGIC, CHUNK_CODEC_VERSION, ENCODING_DELTA];
+    data.extend_from_slice(&(overrides.len() as u16).to_le_bytes());
+    for (index, voxel) in overrides {
+        data.extend_from_slice(&index.to_le_bytes());
+        data.push(voxel.id);
+    }
+    data
+}
+
+// base 只在数据是差量时调用 用来生成原始地形
+pub fn decode_chunk(
+    data: &[u8],
+    base: impl FnOnce() -> Vec<Voxel>,
+) -> Result<Vec<Voxel>, DecodeError> {
+    if data.first() != Some(&CHUNK_MAGIC) {
+        return decode_legacy(data);
+    }
+    if data.len() < 3 {
+        return Err(DecodeError::Truncated);
+    }
+    if data[1] != CHUNK_CODEC_VERSION {
+        return Err(DecodeError::UnknownVersion(data[1]));
+    }
+    let body = &data[3..];
+    let voxels = match data[2] {
+        ENCODING_UNIFORM => {
+            let id = *body.first().ok_or(DecodeError::Truncated)?;
+            vec![Voxel { id }; CHUNK_LEN]
+        }
+        ENCODING_RLE => decode_rle(body)?,
+        ENCODING_PACKED => decode_packed(body)?,
+        ENCODING_DELTA => decode_delta(body, base())?,
+        encoding => return Err(DecodeError::UnknownEncoding(encoding)),
+    };
+    if voxels.len() != CHUNK_LEN {
+        return Err(DecodeError::BadLength(voxels.len()));
+    }
+    Ok(voxels)
+}
+
+fn decode_legacy(data: &[u8]) -> Result<Vec<Voxel>, DecodeError> {
+    let voxels: Vec<Voxel> =
+        bincode::deserialize(data).map_err(|e| DecodeError::Legacy(e.to_string()))?;
+    if voxels.len() != CHUNK_LEN {
+        return Err(DecodeError::BadLength(voxels.len()));
+    }
+    Ok(voxels)
+}
+
+fn build_palette(voxels: &[Voxel]) -> Vec<u8> {
+    let mut seen = [false; 256];
+    let mut palette = Vec::new();
+    for v in voxels {
+        if !seen[v.id as usize] {
+            seen[v.id as usize] = true;
+            palette.push(v.id);
+        }
+    }
+    palette
+}
+
+fn encode_rle(voxels: &[Voxel]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut i = 0;
+    while i < voxels.len() {
+        let id = voxels[i].id;
+        let mut run = 1;
+        while i + run < voxels.len() && voxels[i + run].id == id && run < u16::MAX as usize {
+            run += 1;
+        }
+        data.extend_from_slice(&(run as u16).to_le_bytes());
+        data.push(id);
+        i += run;
+    }
+    data
+}
+
+fn decode_rle(body: &[u8]) -> Result<Vec<Voxel>, DecodeError> {
+    if !body.len().is_multiple_of(3) {
+        return Err(DecodeError::Truncated);
+    }
+    let mut voxels = Vec::with_capacity(CHUNK_LEN);
+    for run in body.chunks(3) {
+        let len = u16::from_le_bytes([run[0], run[1]]) as usize;
+        if voxels.len() + len > CHUNK_LEN {
+            return Err(DecodeError::BadLength(voxels.len() + len));
+        }
+        voxels.extend(std::iter::repeat_n(Voxel { id: run[2] }, len));
+    }
+    Ok(voxels)
+}
+
+fn decode_delta(body: &[u8], mut voxels: Vec<Voxel>) -> Result<Vec<Voxel>, DecodeError> {
+    if body.len() < 2 {
+        return Err(DecodeError::Truncated);
+    }
+    let count = u16::from_le_bytes([body[0], body[1]]) as usize;
+    if body.len() != 2 + count * 3 {
+        return Err(DecodeError::Truncated);
+    }
+    for item in body[2..].chunks(3) {
+        let index = u16::from_le_bytes([item[0], item[1]]);
+        match voxels.get_mut(index as usize) {
+            Some(voxel) => *voxel = Voxel { id: item[2] },
+            None => return Err(DecodeError::BadIndex(index)),
+        }
+    }
+    Ok(voxels)
+}
+
+// 调色板长度对应的索引位数
+fn bits_for(palette_len: usize) -> usize {
+    let mut bits = 1;
+    while (1 << bits) < palette_len {
+        bits += 1;
+    }
+    bits
+}
+
+fn encode_packed(voxels: &[Voxel], palette: &[u8]) -> Vec<u8> {
+    let mut index_of = [0u8; 256];
+    for (i, id) in palette.iter().enumerate() {
+        index_of[*id as usize] = i as u8;
+    }
+    let bits = bits_for(palette.len());
+    let mut data = Vec::with_capacity(1 + palette.len() + (voxels.len() * bits).div_ceil(8));
+    // 调色板最多 256 个 长度使用 len - 1 保存
+    data.push((palette.len() - 1) as u8);
+    data.extend_from_slice(palette);
+    let mut packed = vec![0u8; (voxels.len() * bits).div_ceil(8)];
+    for (i, v) in voxels.iter().enumerate() {
+        let index = index_of[v.id as usize] as usize;
+        for b in 0..bits {
+            if index & (1 << b) != 0 {
+                let bit = i * bits + b;
+                packed[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+    data.extend(packed);
+    data
+}
+
+fn decode_packed(body: &[u8]) -> Result<Vec<Voxel>, DecodeError> {
+    let palette_len = *body.first().ok_or(DecodeError::Truncated)? as usize + 1;
+    if body.len() < 1 + palette_len {
+        return Err(DecodeError::Truncated);
+    }
+    let palette = &body[1..1 + palette_len];
+    let packed = &body[1 + palette_len..];
+    let bits = bits_for(palette_len);
+    if packed.len() != (CHUNK_LEN * bits).div_ceil(8) {
+        return Err(DecodeError::Truncated);
+    }
+    let mut voxels = Vec::with_capacity(CHUNK_LEN);
+    for i in 0..CHUNK_LEN {
+        let mut index = 0usize;
+        for b in 0..bits {
+            let bit = i * bits + b;
+            if packed[bit / 8] & (1 << (bit % 8)) != 0 {
+                index |= 1 << b;
+            }
+        }
+        let id = *palette
+            .get(index)
+            .ok_or(DecodeError::BadPalette(index as u8))?;
+        voxels.push(Voxel { id });
+    }
+    Ok(voxels)
+}
+
+#[test]
+fn test_chunk_codec() {
+    let uniform = vec![Voxel::EMPTY; CHUNK_LEN];
+    let encoded = encode_chunk(&uniform);
+    assert_eq!(encoded.len(), 4);
+    assert!(decode_chunk(&encoded, Vec::new)
+        .unwrap()
+        .iter()
+        .all(|v| v.id == 0));
+
+    // 分层的地形适合游程编码
+    let layered: Vec<Voxel> = (0..CHUNK_LEN)
+        .map(|i| Voxel {
+            id: (SampleShape::delinearize(i as u32)[1] / 4) as u8,
+        })
+        .collect();
+    // 杂乱的数据适合位压缩
+    let noisy: Vec<Voxel> = (0..CHUNK_LEN)
+        .map(|i| Voxel {
+            id: ((i * 7919) % 5) as u8,
+        })
+        .collect();
+    for voxels in [layered.clone(), noisy] {
+        let encoded = encode_chunk(&voxels);
+        assert!(encoded.len() < CHUNK_LEN / 2);
+        let decoded = decode_chunk(&encoded, Vec::new).unwrap();
+        assert!(voxels.iter().zip(decoded.iter()).all(|(a, b)| a.id == b.id));
+    }
+
+    // 差量数据在生成的地形上还原
+    let mut edited = layered.clone();
+    edited[0] = Voxel { id: 9 };
+    edited[CHUNK_LEN - 1] = Voxel::EMPTY;
+    let overrides = diff_chunk(&layered, &edited);
+    assert_eq!(overrides.len(), 2);
+    let decoded = decode_chunk(&encode_delta(&overrides), || layered.clone()).unwrap();
+    assert!(edited.iter().zip(decoded.iter()).all(|(a, b)| a.id == b.id));
+
+    // 老版本的数据仍然可以读取
+    let legacy = bincode::serialize(&uniform).unwrap();
+    assert_eq!(decode_chunk(&legacy, Vec::new).unwrap().len(), CHUNK_LEN);
+
+    let truncated = vec![CHUNK_MAGIC, CHUNK_CODEC_VERSION, ENCODING_PACKED, 3, 0, 1];
+    assert!(decode_chunk(&truncated, Vec::new).is_err());
+}
@@ -1,36 +1,42 @@
 // chunk 修改命令相关
 use bevy::{
     prelude::{
-        warn, AlphaMode, Assets, Color, Commands, GlobalTransform, Input, Last, MaterialMeshBundle,
-        Mesh, MouseButton, Plugin, Res, ResMut, Resource, StandardMaterial, Transform, Update,
-        Vec3,
+        warn, Assets, Commands, EventWriter, GlobalTransform, IVec3, Input, IntoSystemConfigs,
+        Last, Mesh, MouseButton, Plugin, Res, ResMut, Resource, Transform, Update, Vec3,
     },
-    tasks::{AsyncComputeTaskPool, Task},
+    tasks::Task,
 };
 use bevy_rapier3d::prelude::{Collider, RigidBody};
-use ndshape::{ConstShape, ConstShape3u32};
 
 use crate::{
-    chunk::{get_chunk_key_i3_by_vec3, ChunkKey},
+    chunk::ChunkKey,
+    chunk_events::{ChunkMeshed, VoxelChangeCause},
     chunk_generator::ChunkMap,
     collider_generator::{ColliderManager, TerrainPhysics},
+    coordinate::{LocalPos, VoxelPos},
     mesh::{gen_mesh, gen_mesh_water, pick_water},
     mesh_generator::MeshManager,
-    mesh_material::MaterialStorge,
+    occlusion::SectionConnectivity,
     player_ui::HandHolder,
     ray_cast::ChooseCube,
-    voxel::{BasicStone, Voxel, VoxelMaterial},
+    voxel::Voxel,
     voxel_config::MaterailConfiguration,
-    CHUNK_SIZE, CHUNK_SIZE_U32,
+    voxel_world::{RemeshQueue, VoxelWorld},
+    CHUNK_SIZE_U32,
 };
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum ChunkCommands {
     Change {
         chunk_key: ChunkKey,
-        pos: [u32; 3],
+        pos: LocalPos,
         voxel_type: Voxel,
     },
+    // 脚本等大量修改 和同一帧的其他修改一起处理
+    ChangeMany {
+        edits: Vec<(VoxelPos, Voxel)>,
+    },
     UpdateMesh {
         chunk_key: ChunkKey,
     },
@@ -41,193 +47,167 @@ pub struct ChunkCommandsTasks {
     pub tasks: Vec<Task<ChunkCommands>>,
 }
 
-// 处理更新请求
-pub fn do_command_tasks(
+// 处理更新请求 这一帧完成的修改合并成一批交给 VoxelWorld
+pub fn do_command_tasks(mut voxel_world: VoxelWorld, mut tasks: ResMut<ChunkCommandsTasks>) {
+    let mut edits = Vec::new();
+    // 还没有完成的命令留到下一帧
+    tasks.tasks.retain_mut(|task| {
+        match futures_lite::future::block_on(futures_lite::future::poll_once(task)) {
+            Some(ChunkCommands::Change {
+                chunk_key,
+                pos,
+                voxel_type,
+            }) => {
+                edits.push((VoxelPos::from_local(chunk_key, pos), voxel_type));
+                false
+            }
+            Some(ChunkCommands::ChangeMany { edits: many }) => {
+                edits.extend(many);
+                false
+            }
+            Some(ChunkCommands::UpdateMesh { chunk_key }) => {
+                voxel_world.request_remesh(chunk_key);
+                false
+            }
+            None => true,
+        }
+    });
+    if edits.is_empty() {
+        return;
+    }
+    for (pos, e) in voxel_world.set_many(edits, VoxelChangeCause::Command) {
+        warn!("修改方块失败[{:?}]: {:?}", pos, e);
+    }
+}
+
+// 每帧统一重新生成被修改的 section 同一个 section 只处理一次
+#[allow(clippy::too_many_arguments)]
+pub fn remesh_queue_system(
     mut commands: Commands,
     mut chunk_map: ResMut<ChunkMap>,
-    mut tasks: ResMut<ChunkCommandsTasks>,
+    mut remesh_queue: ResMut<RemeshQueue>,
     mut collider_manager: ResMut<ColliderManager>,
     mut mesh_manager: ResMut<MeshManager>,
     material_config: Res<MaterailConfiguration>,
     mut mesh_assets: ResMut<Assets<Mesh>>,
+    mut meshed_events: EventWriter<ChunkMeshed>,
 ) {
-    //FIXME: 首先先分组 同一个chunkMap的数据一起处理合并 后面处理多了再说
-    for ele in tasks.tasks.drain(..) {
-        match futures_lite::future::block_on(futures_lite::future::poll_once(ele)) {
-            Some(chunk_command) => {
-                match chunk_command {
-                    ChunkCommands::Change {
-                        chunk_key,
-                        pos,
-                        voxel_type,
-                    } => {
-                        // 第一步找到地图上的 chunk 数据修改
-                        match chunk_map.map_data.get_mut(&chunk_key) {
-                            Some(voxel) => {
-                                type SampleShape =
-                                    ConstShape3u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
-                                let index = SampleShape::linearize(pos) as usize;
-                                if voxel[index].id == BasicStone::ID {
-                                    warn!("基岩无法破坏");
-                                    return;
-                                }
-                                voxel[index] = voxel_type;
-                                let mut chunk_key_y0 = chunk_key.clone();
-                                chunk_key_y0.0.y = 0;
-                                // todo: 这里可以等重新生成结束后再去 擅长效果应该要好一点
-                                // 要修改的数据
-                                update_mesh(
-                                    chunk_map.as_mut(),
-                                    chunk_key_y0.clone(),
-                                    material_config.clone(),
-                                    mesh_manager.as_mut(),
-                                    mesh_assets.as_mut(),
-                                );
-                                // 重新生成物理
-                                // FIXME: 这里后续也不应该重新生成！
-                                update_collider(
-                                    &mut commands,
-                                    mesh_manager.as_mut(),
-                                    chunk_key_y0.clone(),
-                                    mesh_assets.as_mut(),
-                                    collider_manager.as_mut(),
-                                );
-                            }
-                            None => {
-                                println!("尝试修改没有生成chunk的数据");
-                            }
-                        }
-                        // 然后 擅长 对应的chunk_y 层数据 对应的 chunk_mesh和 和 collider
-                    }
-                    ChunkCommands::UpdateMesh { chunk_key } => {
-                        let mut chunk_key_y0 = chunk_key.clone();
-                        chunk_key_y0.0.y = 0;
-                        if (mesh_manager.mesh_storge.contains_key(&chunk_key_y0)) {
-                            update_mesh(
-                                chunk_map.as_mut(),
-                                chunk_key_y0.clone(),
-                                material_config.clone(),
-                                mesh_manager.as_mut(),
-                                mesh_assets.as_mut(),
-                            );
-                            update_collider(
-                                &mut commands,
-                                mesh_manager.as_mut(),
-                                chunk_key_y0.clone(),
-                                mesh_assets.as_mut(),
-                                collider_manager.as_mut(),
-                            );
-                        }
-                    }
-                }
-            }
-            None => {}
+    for key in remesh_queue.keys.drain() {
+        // 还没有生成过 mesh 的交给 gen_mesh_system
+        // 生成中的 mesh 用的是修改前的数据 作废后重新生成 否则接缝处会留下旧的面
+        if !mesh_manager.fast_key.contains(&key) || mesh_manager.cancel_task(key) {
+            continue;
+        }
+        let meshed = remesh_section(
+            &mut commands,
+            chunk_map.as_mut(),
+            key,
+            material_config.clone(),
+            mesh_manager.as_mut(),
+            mesh_assets.as_mut(),
+            collider_manager.as_mut(),
+        );
+        // 同步生成的 mesh 和 update_mesh_system 一样通知
+        // 交给 gen_mesh_system 重新生成的 等生成好之后再通知
+        if meshed {
+            meshed_events.send(ChunkMeshed { chunk_key: key });
         }
     }
 }
 
 pub fn build_or_break(
-    mut mouse_button_input: ResMut<Input<MouseButton>>,
-    mut choose_cube: ResMut<ChooseCube>,
-    mut tasks: ResMut<ChunkCommandsTasks>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    choose_cube: Res<ChooseCube>,
+    mut voxel_world: VoxelWorld,
     hand_holder: Res<HandHolder>,
 ) {
-    let pool = AsyncComputeTaskPool::get();
-
     if mouse_button_input.just_pressed(MouseButton::Left) {
         // 这里按下了鼠标左键
-        if let Some(pos) = choose_cube.center {
-            // 点转成 chunk_key 和 x, y, z 的方法？
-            let (chunk_key, xyz) = vec3_to_chunk_key_any_xyz(pos);
-            println!("左键点击 要打击的方块是[{:?}][{:?}]", chunk_key, xyz);
-            let task = pool.spawn(async move {
-                ChunkCommands::Change {
-                    chunk_key: chunk_key,
-                    pos: xyz,
-                    voxel_type: Voxel::EMPTY,
-                }
-            });
-            tasks.tasks.push(task);
-            // FIXME: 遇到的边界问题 代码不优雅可以优化
-            // 注意这里 有顺序问题!
-            if xyz[0] == 0 {
-                let mut new_chunk_key_i3 = chunk_key.0.clone();
-                new_chunk_key_i3.x -= 1;
-                let task_new = pool.spawn(async move {
-                    ChunkCommands::UpdateMesh {
-                        chunk_key: ChunkKey(new_chunk_key_i3),
-                    }
-                });
-                tasks.tasks.push(task_new);
-            }
-            if xyz[0] == CHUNK_SIZE_U32 - 1 {
-                let mut new_chunk_key_i3 = chunk_key.0.clone();
-                new_chunk_key_i3.x += 1;
-                let task_new = pool.spawn(async move {
-                    ChunkCommands::UpdateMesh {
-                        chunk_key: ChunkKey(new_chunk_key_i3),
-                    }
-                });
-                tasks.tasks.push(task_new);
-            }
-            if xyz[2] == 0 {
-                let mut new_chunk_key_i3 = chunk_key.0.clone();
-                new_chunk_key_i3.z -= 1;
-                let task_new = pool.spawn(async move {
-                    ChunkCommands::UpdateMesh {
-                        chunk_key: ChunkKey(new_chunk_key_i3),
-                    }
-                });
-                tasks.tasks.push(task_new);
-            }
-            if xyz[2] == CHUNK_SIZE_U32 - 1 {
-                let mut new_chunk_key_i3 = chunk_key.0.clone();
-                new_chunk_key_i3.z += 1;
-                let task_new = pool.spawn(async move {
-                    ChunkCommands::UpdateMesh {
-                        chunk_key: ChunkKey(new_chunk_key_i3),
-                    }
-                });
-                tasks.tasks.push(task_new);
+        if let Some(voxel_pos) = choose_cube.center {
+            println!("左键点击 要打击的方块是[{:?}]", voxel_pos);
+            if let Err(e) = voxel_world.set_voxel(voxel_pos, Voxel::EMPTY, VoxelChangeCause::Break)
+            {
+                warn!("破坏方块失败: {:?}", e);
             }
         }
     }
 
     if mouse_button_input.just_pressed(MouseButton::Right) {
         // 这里按下了鼠标右边键
-        if let Some(pos) = choose_cube.out_center {
+        if let Some(voxel_pos) = choose_cube.out_center {
             if let Some(staff) = hand_holder.0.clone() {
                 if let Some(voxel_type) = staff.voxel {
-                    let (chunk_key, xyz) = vec3_to_chunk_key_any_xyz(pos);
-                    println!("左键点击 要添加的方块是[{:?}][{:?}]", chunk_key, xyz);
-                    let task = pool.spawn(async move {
-                        ChunkCommands::Change {
-                            chunk_key: chunk_key,
-                            pos: xyz,
-                            voxel_type: voxel_type,
-                        }
-                    });
-                    tasks.tasks.push(task);
+                    println!("右键点击 要添加的方块是[{:?}]", voxel_pos);
+                    if let Err(e) =
+                        voxel_world.set_voxel(voxel_pos, voxel_type, VoxelChangeCause::Place)
+                    {
+                        warn!("放置方块失败: {:?}", e);
+                    }
                 } else {
                     println!("当前类型不能放置")
                 }
             } else {
                 println!("当前手上没有物品")
             }
-            // 点转成 chunk_key 和 x, y, z 的方法？
         }
     }
 }
 
+// 修改的体素在 section 的边界上时 相邻 section 的面也会变化
+pub fn boundary_neighbours(chunk_key: ChunkKey, pos: LocalPos) -> Vec<ChunkKey> {
+    let pos = pos.to_array();
+    let mut result = Vec::new();
+    for axis in 0..3 {
+        let mut offset = IVec3::ZERO;
+        if pos[axis] == 0 {
+            offset[axis] = -1;
+        } else if pos[axis] == CHUNK_SIZE_U32 - 1 {
+            offset[axis] = 1;
+        } else {
+            continue;
+        }
+        result.push(ChunkKey(chunk_key.0 + offset));
+    }
+    result
+}
+
+// 重新生成一个 section 的 mesh 和碰撞体 返回 mesh 是否已经更新
+pub fn remesh_section(
+    commands: &mut Commands,
+    chunk_map: &mut ChunkMap,
+    chunk_key: ChunkKey,
+    material_config: MaterailConfiguration,
+    mesh_manager: &mut MeshManager,
+    mesh_assets: &mut Assets<Mesh>,
+    collider_manager: &mut ColliderManager,
+) -> bool {
+    let meshed = update_mesh(
+        commands,
+        chunk_map,
+        chunk_key,
+        material_config,
+        mesh_manager,
+        mesh_assets,
+    );
+    update_collider(
+        commands,
+        mesh_manager,
+        chunk_key,
+        mesh_assets,
+        collider_manager,
+    );
+    meshed
+}
+
 pub fn update_collider(
     commands: &mut Commands,
     mesh_manager: &mut MeshManager,
-    chunk_key_y0: ChunkKey,
+    chunk_key: ChunkKey,
     mesh_assets: &mut Assets<Mesh>,
     collider_manager: &mut ColliderManager,
 ) {
-    if (mesh_manager.mesh_storge.contains_key(&chunk_key_y0)) {
-        let mesh_handle = mesh_manager.mesh_storge.get(&chunk_key_y0).unwrap();
+    if mesh_manager.mesh_storge.contains_key(&chunk_key) {
+        let mesh_handle = mesh_manager.mesh_storge.get(&chunk_key).unwrap();
         if let Some(mesh) = mesh_assets.get(mesh_handle) {
             // 生成 collider 碰撞体
             if let Some(positions) = mesh
@@ -237,68 +217,90 @@ pub fn update_collider(
             {
                 let indices: Vec<u32> = mesh.indices().unwrap().iter().map(|x| x as u32).collect();
                 let collider_vertices: Vec<Vec3> =
-                    positions.iter().cloned().map(|p| Vec3::from(p)).collect();
+                    positions.iter().cloned().map(Vec3::from).collect();
                 let collider_indices: Vec<[u32; 3]> =
                     indices.chunks(3).map(|i| [i[0], i[1], i[2]]).collect();
                 let collider: Collider = Collider::trimesh(collider_vertices, collider_indices);
                 let entity = commands
                     .spawn((
                         TerrainPhysics,
-                        Transform::from_xyz(
-                            (chunk_key_y0.0.x * CHUNK_SIZE) as f32 - CHUNK_SIZE as f32 / 2.0 - 1.0,
-                            -128.0 + CHUNK_SIZE as f32 / 2.0,
-                            (chunk_key_y0.0.z * CHUNK_SIZE) as f32 - CHUNK_SIZE as f32 / 2.0 - 1.0,
-                        ),
+                        Transform::from_translation(chunk_key.mesh_origin()),
                         GlobalTransform::default(),
                     ))
                     .insert(RigidBody::Fixed)
                     .insert(collider)
                     .id();
-                if let Some(old_id) = collider_manager.entities.insert(chunk_key_y0, entity) {
+                if let Some(old_id) = collider_manager.entities.insert(chunk_key, entity) {
                     commands.entity(old_id).despawn();
                 }
             }
         }
+    } else if let Some(old_id) = collider_manager.entities.remove(&chunk_key) {
+        // mesh 等待重新生成 碰撞体之后由 TerrainPhysicsPlugin 生成
+        commands.entity(old_id).despawn();
     }
 }
 
+// 原来的 mesh 被删除 等待 gen_mesh_system 重新生成时返回 false
 pub fn update_mesh(
+    commands: &mut Commands,
     chunk_map: &mut ChunkMap,
-    chunk_key_y0: ChunkKey,
+    chunk_key: ChunkKey,
     material_config: MaterailConfiguration,
     mesh_manager: &mut MeshManager,
     mesh_assets: &mut Assets<Mesh>,
-) {
-    let volexs: Vec<Voxel> = chunk_map.get_with_neighbor_full_y(chunk_key_y0);
-    match gen_mesh(volexs.to_owned(), material_config.clone()) {
+) -> bool {
+    let volexs: Vec<Voxel> = chunk_map.get_with_neighbor(chunk_key);
+    let render_mesh = gen_mesh(volexs.to_owned(), material_config.clone());
+    mesh_manager
+        .connectivity
+        .insert(chunk_key, SectionConnectivity::from_padded(&volexs));
+    let water_mesh = gen_mesh_water(pick_water(volexs), material_config);
+    if (render_mesh.is_some() && !mesh_manager.mesh_storge.contains_key(&chunk_key))
+        || (water_mesh.is_some() && !mesh_manager.water_mesh_storge.contains_key(&chunk_key))
+    {
+        // 原来是空的 section 没有可以替换的 mesh 删除后交给 gen_mesh_system 重新生成
+        if let Some(entity) = mesh_manager.entities.remove(&chunk_key) {
+            commands.entity(entity).despawn();
+        }
+        if let Some(entity) = mesh_manager.water_entities.remove(&chunk_key) {
+            commands.entity(entity).despawn();
+        }
+        mesh_manager.mesh_storge.remove(&chunk_key);
+        mesh_manager.water_mesh_storge.remove(&chunk_key);
+        mesh_manager.fast_key.remove(&chunk_key);
+        return false;
+    }
+    match render_mesh {
         Some(render_mesh) => {
-            let mesh_handle = mesh_manager.mesh_storge.get(&chunk_key_y0).unwrap();
+            let mesh_handle = mesh_manager.mesh_storge.get(&chunk_key).unwrap();
             if let Some(mesh) = mesh_assets.get_mut(mesh_handle) {
                 *mesh = render_mesh;
             }
-            // 没有生成mesh就不管反正后面要生成
         }
-        None => {}
+        None => {
+            // section 里的方块都被挖空了
+            if let Some(entity) = mesh_manager.entities.remove(&chunk_key) {
+                commands.entity(entity).despawn();
+            }
+            mesh_manager.mesh_storge.remove(&chunk_key);
+        }
     };
-    match gen_mesh_water(pick_water(volexs.clone()), material_config.clone()) {
+    match water_mesh {
         Some(water_mesh) => {
-            let mesh_handle = mesh_manager.water_mesh_storge.get(&chunk_key_y0).unwrap();
+            let mesh_handle = mesh_manager.water_mesh_storge.get(&chunk_key).unwrap();
             if let Some(mesh) = mesh_assets.get_mut(mesh_handle) {
                 *mesh = water_mesh;
             }
         }
-        None => {}
+        None => {
+            if let Some(entity) = mesh_manager.water_entities.remove(&chunk_key) {
+                commands.entity(entity).despawn();
+            }
+            mesh_manager.water_mesh_storge.remove(&chunk_key);
+        }
     }
-}
-
-pub fn vec3_to_chunk_key_any_xyz(pos: Vec3) -> (ChunkKey, [u32; 3]) {
-    println!("此时的位置是: {:?}", pos);
-    let chunk_key = ChunkKey(get_chunk_key_i3_by_vec3(pos));
-    let x = (pos.x - (chunk_key.0.x * CHUNK_SIZE) as f32 + CHUNK_SIZE as f32 / 2. - 0.5) as u32;
-    let y = (pos.y - (chunk_key.0.y * CHUNK_SIZE) as f32 + CHUNK_SIZE as f32 / 2. - 0.5) as u32;
-    let z = (pos.z - (chunk_key.0.z * CHUNK_SIZE) as f32 + CHUNK_SIZE as f32 / 2. - 0.5) as u32;
-
-    return (chunk_key, [x, y, z]);
+    true
 }
 
 pub struct ChunkCommandsPlugin;
@@ -307,13 +309,29 @@ impl Plugin for ChunkCommandsPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         // 初始化资源
         app.insert_resource(ChunkCommandsTasks { tasks: Vec::new() })
+            .init_resource::<RemeshQueue>()
             .add_systems(Update, build_or_break)
-            .add_systems(Last, do_command_tasks);
+            .add_systems(Last, (do_command_tasks, remesh_queue_system).chain());
     }
 }
 
 #[test]
-fn test_chunk_key() {
-    let (key, xyz) = vec3_to_chunk_key_any_xyz(Vec3::new(2.5, 24.5, 0.5));
-    print!("{:?},{:?}", key, xyz);
+fn test_boundary_neighbours() {
+    use bevy::prelude::UVec3;
+
+    let key = ChunkKey(IVec3::new(1, -2, 3));
+    assert!(boundary_neighbours(key, LocalPos(UVec3::new(5, 6, 7))).is_empty());
+    let keys = boundary_neighbours(key, LocalPos(UVec3::new(0, 6, CHUNK_SIZE_U32 - 1)));
+    assert_eq!(
+        keys,
+        vec![
+            ChunkKey(IVec3::new(0, -2, 3)),
+            ChunkKey(IVec3::new(1, -2, 4))
+        ]
+    );
+    // 上下方向的边界也要更新
+    assert_eq!(
+        boundary_neighbours(key, LocalPos(UVec3::new(3, CHUNK_SIZE_U32 - 1, 4))),
+        vec![ChunkKey(IVec3::new(1, -1, 3))]
+    );
 }
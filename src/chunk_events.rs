@@ -0,0 +1,57 @@
+// chunk 和方块的生命周期事件
+// 声音 粒子 统计 联机等功能监听这些事件 不需要修改加载和生成的代码
+use bevy::prelude::{App, Event, Plugin};
+
+use crate::{chunk::ChunkKey, coordinate::VoxelPos, voxel::Voxel};
+
+// chunk 的数据加载完成 (从数据库读取或者新生成)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Event)]
+pub struct ChunkLoaded {
+    pub chunk_key: ChunkKey,
+}
+
+// section 的 mesh 生成完成 空的 section 也会发送
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Event)]
+pub struct ChunkMeshed {
+    pub chunk_key: ChunkKey,
+}
+
+// chunk 的数据从内存中卸载
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Event)]
+pub struct ChunkUnloaded {
+    pub chunk_key: ChunkKey,
+}
+
+// 方块被修改的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoxelChangeCause {
+    // 玩家破坏
+    Break,
+    // 玩家放置
+    Place,
+    // 通过 ChunkCommands 修改 比如控制台或者脚本
+    Command,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Event)]
+pub struct VoxelChanged {
+    pub pos: VoxelPos,
+    pub old: Voxel,
+    pub new: Voxel,
+    pub cause: VoxelChangeCause,
+}
+
+pub struct ChunkEventsPlugin;
+
+impl Plugin for ChunkEventsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ChunkLoaded>()
+            .add_event::<ChunkMeshed>()
+            .add_event::<ChunkUnloaded>()
+            .add_event::<VoxelChanged>();
+    }
+}
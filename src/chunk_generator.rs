@@ -1,23 +1,108 @@
-use bevy::prelude::{IVec3, Res, ResMut, Resource};
+use std::collections::HashSet;
+
+use bevy::{
+    prelude::{
+        warn, EventWriter, GlobalTransform, IVec3, Local, Query, Res, ResMut, Resource, With,
+    },
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use controller::controller::CameraTag;
 use ndshape::{ConstShape, ConstShape3u32};
 
 use crate::{
-    chunk::{find_chunk_keys_by_shpere_to_full_height, ChunkKey, NeighbourOffest},
+    chunk::{sort_by_load_priority, ChunkKey, NEIGHBOUR_SIDES},
+    chunk_events::{ChunkLoaded, ChunkUnloaded},
+    chunk_loader::ChunkAnchors,
     clip_spheres::ClipSpheres,
     map_database::MapDataBase,
+    mesh::PaddedChunkShape,
     voxel::Voxel,
-    SmallKeyHashMap, CHUNK_SIZE, CHUNK_SIZE_ADD_2_U32, CHUNK_SIZE_U32,
+    world_save::AutoSaveState,
+    SmallKeyHashMap, CHUNK_SIZE_U32,
 };
 
+// ChunkMap 的大小限制
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct ChunkMapLimit {
+    // 超出视野多少个 chunk 后才卸载 避免在边界来回走动时反复加载
+    pub hysteresis: i32,
+    // 最多保留的 chunk 个数 超出时卸载最久没有使用的
+    pub max_chunks: usize,
+}
+
+impl Default for ChunkMapLimit {
+    fn default() -> Self {
+        Self {
+            hysteresis: 2,
+            max_chunks: 16384,
+        }
+    }
+}
+
+// 同时在后台读取或生成的 chunk 个数
+pub const MAX_LOAD_TASKS: usize = 256;
+
+// 后台加载中的 chunk
+#[derive(Resource, Default)]
+pub struct ChunkLoadTasks {
+    pub tasks: SmallKeyHashMap<ChunkKey, Task<Vec<Voxel>>>,
+}
+
 #[derive(Debug, Clone, Default, Resource)]
 pub struct ChunkMap {
     pub map_data: SmallKeyHashMap<ChunkKey, Vec<Voxel>>,
+    // 加载后被修改过 还没有保存的 chunk
+    pub dirty: HashSet<ChunkKey>,
+    // 最后一次使用的帧 用来按 LRU 卸载
+    pub last_used: SmallKeyHashMap<ChunkKey, u64>,
+    pub frame: u64,
 }
 
 impl ChunkMap {
     pub fn new() -> Self {
         let data_map = SmallKeyHashMap::<ChunkKey, Vec<Voxel>>::new();
-        Self { map_data: data_map }
+        Self {
+            map_data: data_map,
+            dirty: HashSet::new(),
+            last_used: SmallKeyHashMap::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn touch(&mut self, chunk_key: ChunkKey) {
+        self.last_used.insert(chunk_key, self.frame);
+    }
+
+    // 卸载 chunk 返回数据和是否还有没保存的修改
+    pub fn remove_chunk(&mut self, chunk_key: ChunkKey) -> Option<(Vec<Voxel>, bool)> {
+        self.last_used.remove(&chunk_key);
+        let dirty = self.dirty.remove(&chunk_key);
+        self.map_data
+            .remove(&chunk_key)
+            .map(|voxels| (voxels, dirty))
+    }
+
+    pub fn mark_dirty(&mut self, chunk_key: ChunkKey) {
+        self.dirty.insert(chunk_key);
+    }
+
+    // 取出需要保存的数据 并清除标记
+    pub fn take_dirty(&mut self) -> Vec<(ChunkKey, Vec<Voxel>)> {
+        let mut result = Vec::new();
+        for key in self.dirty.drain() {
+            if let Some(voxels) = self.map_data.get(&key) {
+                result.push((key, voxels.clone()));
+            }
+        }
+        result
+    }
+
+    // 自己和六个方向的邻居全部加载后才能生成 mesh
+    pub fn section_ready(&self, chunk_key: ChunkKey) -> bool {
+        self.map_data.contains_key(&chunk_key)
+            && NEIGHBOUR_SIDES
+                .iter()
+                .all(|side| self.map_data.contains_key(&ChunkKey(chunk_key.0 + *side)))
     }
 
     pub fn get(&self, key: ChunkKey) -> Option<&Vec<Voxel>> {
@@ -26,6 +111,7 @@ impl ChunkMap {
 
     pub fn write_chunk(&mut self, chunk_key: ChunkKey, item: Vec<Voxel>) {
         self.map_data.insert(chunk_key, item);
+        self.touch(chunk_key);
     }
 
     pub fn get_by_index(volex: Option<&Vec<Voxel>>, index: u32) -> Voxel {
@@ -35,107 +121,193 @@ impl ChunkMap {
         }
     }
 
-    pub fn get_with_neighbor_full_y(&mut self, chunk_key: ChunkKey) -> Vec<Voxel> {
-        let mut result = Vec::new();
-        type SampleShape = ConstShape3u32<CHUNK_SIZE_ADD_2_U32, 256, CHUNK_SIZE_ADD_2_U32>;
-        type DataShape = ConstShape3u32<CHUNK_SIZE_ADD_2_U32, CHUNK_SIZE_U32, CHUNK_SIZE_ADD_2_U32>;
-        let mut map: SmallKeyHashMap<i32, Vec<Voxel>> = SmallKeyHashMap::new();
-
-        let last_inex = -128 / CHUNK_SIZE + 1;
-
-        for y_offset in last_inex..=128 / CHUNK_SIZE {
-            let mut new_key = chunk_key.clone();
-            new_key.0.y = y_offset;
-            let layer_data = self.get_layer_neighbors(new_key);
-            map.insert(y_offset, layer_data);
-        }
-
-        for i in 0..SampleShape::SIZE {
-            let [x, y, z] = SampleShape::delinearize(i);
-            let layer = y / CHUNK_SIZE_U32;
-            let layer_index: i32 = (layer as i32) + last_inex;
-            let data = map.get(&(layer_index as i32));
-            let index = DataShape::linearize([x, y % CHUNK_SIZE_U32, z]);
-            result.push(Self::get_by_index(data, index));
-        }
-
-        result
-    }
-
-    fn get_layer_neighbors(&mut self, chunk_key: ChunkKey) -> Vec<Voxel> {
-        let voxels = self.get(chunk_key);
-
-        type SampleShape =
-            ConstShape3u32<CHUNK_SIZE_ADD_2_U32, CHUNK_SIZE_U32, CHUNK_SIZE_ADD_2_U32>;
+    // 生成 mesh 用的数据 四周各有一格来自六个方向的邻居 棱和角不会用到
+    pub fn get_with_neighbor(&self, chunk_key: ChunkKey) -> Vec<Voxel> {
         type DataShape = ConstShape3u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
+        let mut map: SmallKeyHashMap<IVec3, &Vec<Voxel>> = SmallKeyHashMap::new();
+        for side in [IVec3::ZERO].iter().chain(NEIGHBOUR_SIDES.iter()) {
+            if let Some(v) = self.get(ChunkKey(chunk_key.0 + *side)) {
+                map.insert(*side, v);
+            }
+        }
 
-        // let py = &IVec3::new(0, 1, 0);
-        // let ny = &IVec3::new(0, -1, 0);
-        let px = &IVec3::new(1, 0, 0);
-        let nx = &IVec3::new(-1, 0, 0);
-        let pz = &IVec3::new(0, 0, 1);
-        let nz = &IVec3::new(0, 0, -1);
-
-        let offsets = vec![px, nx, pz, nz];
-        let mut map: SmallKeyHashMap<IVec3, Vec<Voxel>> = SmallKeyHashMap::new();
-        for ele in offsets {
-            let new_key = ChunkKey(chunk_key.0 + ele.clone());
-            let _ = match self.get(new_key) {
-                Some(v) => {
-                    map.insert(ele.clone(), v.clone());
+        let mut result = Vec::with_capacity(PaddedChunkShape::SIZE as usize);
+        for i in 0..PaddedChunkShape::SIZE {
+            let [x, y, z] = PaddedChunkShape::delinearize(i);
+            let mut side = IVec3::ZERO;
+            let mut local = [0u32; 3];
+            for (axis, value) in [x, y, z].into_iter().enumerate() {
+                if value == 0 {
+                    side[axis] = -1;
+                    local[axis] = CHUNK_SIZE_U32 - 1;
+                } else if value == CHUNK_SIZE_U32 + 1 {
+                    side[axis] = 1;
+                    local[axis] = 0;
+                } else {
+                    local[axis] = value - 1;
                 }
-                None => (),
-            };
-        }
-        let mut result = Vec::new();
-        for i in 0..SampleShape::SIZE {
-            let [x, y, z] = SampleShape::delinearize(i);
-            if z != 0 && z != CHUNK_SIZE_U32 + 1 && x == CHUNK_SIZE_U32 + 1 {
-                // y轴
-                let index = DataShape::linearize([0, y, z - 1]);
-                let v: Option<&Vec<Voxel>> = map.get(px);
-                result.push(Self::get_by_index(v, index));
-            } else if z != 0 && z != CHUNK_SIZE_U32 + 1 && x == 0 {
-                let index = DataShape::linearize([CHUNK_SIZE_U32 - 1, y, z - 1]);
-                let v = map.get(nx);
-                result.push(Self::get_by_index(v, index));
-            } else if x != 0 && x != CHUNK_SIZE_U32 + 1 && z == CHUNK_SIZE_U32 + 1 {
-                // z轴
-                let index = DataShape::linearize([x - 1, y, 0]);
-                let v = map.get(pz);
-                result.push(Self::get_by_index(v, index));
-            } else if x != 0 && x != CHUNK_SIZE_U32 + 1 && z == 0 {
-                let index = DataShape::linearize([x - 1, y, CHUNK_SIZE_U32 - 1]);
-                let v = map.get(nz);
-                result.push(Self::get_by_index(v, index));
-            } else if x > 0 && x < CHUNK_SIZE_U32 + 1 && z > 0 && z < CHUNK_SIZE_U32 + 1 {
-                let index = DataShape::linearize([x - 1, y, z - 1]);
-                result.push(Self::get_by_index(voxels, index));
-            } else {
+            }
+            if side.x.abs() + side.y.abs() + side.z.abs() > 1 {
                 result.push(Voxel::EMPTY);
+                continue;
             }
+            let index = DataShape::linearize(local);
+            result.push(Self::get_by_index(map.get(&side).copied(), index));
         }
 
         result
     }
 }
 
+// 数据库读取和地形生成都放到后台 完成后再合并到 ChunkMap
+// 加载所有锚点周围的 chunk 离相机近的 (视线前方的) chunk 先加载
 pub fn chunk_generate_system(
     mut chunk_map: ResMut<ChunkMap>,
-    neighbour_offest: Res<NeighbourOffest>,
+    chunk_anchors: Res<ChunkAnchors>,
     clip_spheres: Res<ClipSpheres>,
-    mut db: ResMut<MapDataBase>,
+    db: Res<MapDataBase>,
+    save_state: Res<AutoSaveState>,
+    mut load_tasks: ResMut<ChunkLoadTasks>,
+    camera: Query<&GlobalTransform, With<CameraTag>>,
+) {
+    chunk_map.frame += 1;
+    let pool = AsyncComputeTaskPool::get();
+    let mut missing = Vec::new();
+    for &key in chunk_anchors.load_keys.iter() {
+        if chunk_map.map_data.contains_key(&key) {
+            chunk_map.touch(key);
+        } else if !load_tasks.tasks.contains_key(&key) && !save_state.saving.contains(&key) {
+            // 正在写入数据库的等写完再读取
+            missing.push(key);
+        }
+    }
+    let free = MAX_LOAD_TASKS.saturating_sub(load_tasks.tasks.len());
+    if free == 0 || missing.is_empty() {
+        return;
+    }
+    let forward = camera.get_single().ok().map(|tfr| tfr.forward());
+    sort_by_load_priority(&mut missing, clip_spheres.new_sphere.center, forward);
+    for key in missing.into_iter().take(free) {
+        // 卸载后还没有写入数据库的 直接使用等待保存的数据
+        let task = match save_state.pending.get(&key) {
+            Some(voxels) => {
+                let voxels = voxels.clone();
+                pool.spawn(async move { voxels })
+            }
+            None => {
+                let db = db.clone();
+                pool.spawn(async move { db.find_by_chunk_key(key) })
+            }
+        };
+        load_tasks.tasks.insert(key, task);
+    }
+}
+
+pub fn chunk_load_merge_system(
+    mut chunk_map: ResMut<ChunkMap>,
+    mut load_tasks: ResMut<ChunkLoadTasks>,
+    mut loaded_events: EventWriter<ChunkLoaded>,
+) {
+    load_tasks.tasks.retain(|key, task| {
+        match futures_lite::future::block_on(futures_lite::future::poll_once(task)) {
+            Some(voxels) => {
+                chunk_map.write_chunk(*key, voxels);
+                loaded_events.send(ChunkLoaded { chunk_key: *key });
+                false
+            }
+            None => true,
+        }
+    });
+}
+
+// 卸载视野外的 chunk 没有保存的修改交给自动保存写回数据库
+pub fn chunk_unload_system(
+    mut chunk_map: ResMut<ChunkMap>,
+    limit: Res<ChunkMapLimit>,
+    chunk_anchors: Res<ChunkAnchors>,
+    mut save_state: ResMut<AutoSaveState>,
+    mut unloaded_events: EventWriter<ChunkUnloaded>,
+    mut warned_load_size: Local<usize>,
 ) {
-    find_chunk_keys_by_shpere_to_full_height(
-        clip_spheres.new_sphere,
-        neighbour_offest.0.clone(),
-        move |key| {
-            // 这里要判断一下获取的方法
-            // chunk_map.gen_chunk_data(key);
-            if !chunk_map.map_data.contains_key(&key) {
-                //  这里可以判断一下是否是 已经加载的数据
-                chunk_map.write_chunk(key, db.find_by_chunk_key(key));
+    // 视野内的 chunk 每帧都会用到 上限比视野小时无法卸载 按视野的大小处理
+    let load_size = chunk_anchors.load_keys.len();
+    let max_chunks = limit.max_chunks.max(load_size);
+    if load_size > limit.max_chunks && *warned_load_size != load_size {
+        warn!(
+            "视野内有 {} 个chunk 超过了上限 {} 按视野的大小保留",
+            load_size, limit.max_chunks
+        );
+    }
+    *warned_load_size = load_size;
+
+    // 加载时多了一圈邻居 所以再加一
+    let keep_distance = limit.hysteresis + 1;
+    let mut to_remove: Vec<ChunkKey> = chunk_map
+        .map_data
+        .keys()
+        .filter(|key| !chunk_anchors.contains(**key, keep_distance))
+        .cloned()
+        .collect();
+
+    // 超出上限时按最后使用的时间卸载 本帧用到的不卸载
+    let over = (chunk_map.map_data.len() - to_remove.len()).saturating_sub(max_chunks);
+    if over > 0 {
+        let frame = chunk_map.frame;
+        let removing: HashSet<ChunkKey> = to_remove.iter().cloned().collect();
+        let mut candidates: Vec<(u64, ChunkKey)> = chunk_map
+            .last_used
+            .iter()
+            .filter(|(key, used)| **used < frame && !removing.contains(*key))
+            .map(|(key, used)| (*used, *key))
+            .collect();
+        candidates.sort_by_key(|(used, _)| *used);
+        to_remove.extend(candidates.into_iter().take(over).map(|(_, key)| key));
+    }
+
+    for chunk_key in to_remove {
+        if let Some((voxels, dirty)) = chunk_map.remove_chunk(chunk_key) {
+            if dirty {
+                save_state.queue_unloaded(chunk_key, voxels);
             }
-        },
+            unloaded_events.send(ChunkUnloaded { chunk_key });
+        }
+    }
+}
+
+#[test]
+fn test_get_with_neighbor() {
+    type DataShape = ConstShape3u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
+    let size = DataShape::SIZE as usize;
+    let mut chunk_map = ChunkMap::new();
+    let key = ChunkKey(IVec3::new(0, -9, 0));
+    chunk_map.write_chunk(key, vec![Voxel::FILLED; size]);
+    let mut up = vec![Voxel::EMPTY; size];
+    up[DataShape::linearize([3, 0, 4]) as usize] = Voxel::FILLED;
+    chunk_map.write_chunk(ChunkKey(key.0 + IVec3::Y), up);
+    assert!(!chunk_map.section_ready(key));
+
+    let voxels = chunk_map.get_with_neighbor(key);
+    assert_eq!(voxels.len(), PaddedChunkShape::SIZE as usize);
+    let top = CHUNK_SIZE_U32 + 1;
+    assert_eq!(
+        voxels[PaddedChunkShape::linearize([4, top, 5]) as usize].id,
+        Voxel::FILLED.id
+    );
+    assert_eq!(
+        voxels[PaddedChunkShape::linearize([5, top, 5]) as usize].id,
+        Voxel::EMPTY.id
+    );
+    assert_eq!(
+        voxels[PaddedChunkShape::linearize([1, 1, 1]) as usize].id,
+        Voxel::FILLED.id
+    );
+    // 没有加载的邻居和棱角都是空的
+    assert_eq!(
+        voxels[PaddedChunkShape::linearize([0, 5, 5]) as usize].id,
+        Voxel::EMPTY.id
+    );
+    assert_eq!(
+        voxels[PaddedChunkShape::linearize([0, 0, 5]) as usize].id,
+        Voxel::EMPTY.id
     );
 }
@@ -0,0 +1,142 @@
+// 让世界保持加载的锚点 任何带有 ChunkLoader 的实体周围都会加载 chunk
+// 比如其他玩家 观察者相机 或者脚本控制的相机
+use std::collections::HashSet;
+
+use bevy::prelude::{Component, DetectChanges, IVec3, Query, Res, ResMut, Resource, Transform};
+
+use crate::{
+    chunk::{generate_offset_resoure, offsets_with_neighbour_ring, ChunkKey, NeighbourOffest},
+    coordinate::WorldPos,
+    view_distance::ViewDistance,
+};
+
+#[derive(Debug, Clone, Copy, Component)]
+pub struct ChunkLoader {
+    // 自己的视野范围 None 时跟随设置中的视野距离
+    pub view: Option<ViewDistance>,
+}
+
+impl ChunkLoader {
+    pub fn follow_view() -> Self {
+        Self { view: None }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_radius(radius: f32, height: f32) -> Self {
+        Self {
+            view: Some(ViewDistance::new(radius, height)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkAnchor {
+    pub center: IVec3,
+    pub radius: f32,
+    pub height: f32,
+}
+
+impl ChunkAnchor {
+    pub fn view(&self) -> ViewDistance {
+        ViewDistance {
+            radius: self.radius,
+            height: self.height,
+        }
+    }
+}
+
+// 所有锚点 和它们视野的并集 只在锚点移动到其他 chunk 或者视野改变时更新
+#[derive(Debug, Default, Resource)]
+pub struct ChunkAnchors {
+    pub anchors: Vec<ChunkAnchor>,
+    // 需要生成 mesh 的 chunk
+    pub keys: Vec<ChunkKey>,
+    // 再加一圈邻居 需要加载数据的 chunk
+    pub load_keys: Vec<ChunkKey>,
+}
+
+impl ChunkAnchors {
+    // 是否在任意一个锚点的视野内 extra 是额外的 chunk 个数
+    pub fn contains(&self, chunk_key: ChunkKey, extra: i32) -> bool {
+        self.anchors
+            .iter()
+            .any(|anchor| anchor.view().contains(chunk_key.0 - anchor.center, extra))
+    }
+
+    fn rebuild(&mut self, neighbour_offest: &NeighbourOffest, view_distance: &ViewDistance) {
+        let mut keys = HashSet::new();
+        let mut load_keys = HashSet::new();
+        for anchor in self.anchors.iter() {
+            let custom;
+            let offsets =
+                if anchor.radius == view_distance.radius && anchor.height == view_distance.height {
+                    &neighbour_offest.0
+                } else {
+                    custom = generate_offset_resoure(anchor.radius, anchor.height);
+                    &custom.0
+                };
+            keys.extend(offsets.iter().map(|&ele| ChunkKey(anchor.center + ele)));
+            load_keys.extend(
+                offsets_with_neighbour_ring(offsets)
+                    .into_iter()
+                    .map(|ele| ChunkKey(anchor.center + ele)),
+            );
+        }
+        self.keys = keys.into_iter().collect();
+        self.load_keys = load_keys.into_iter().collect();
+    }
+}
+
+pub fn update_chunk_anchors_system(
+    mut chunk_anchors: ResMut<ChunkAnchors>,
+    neighbour_offest: Res<NeighbourOffest>,
+    view_distance: Res<ViewDistance>,
+    query: Query<(&Transform, &ChunkLoader)>,
+) {
+    let anchors: Vec<ChunkAnchor> = query
+        .iter()
+        .map(|(tfr, loader)| {
+            let view = loader.view.unwrap_or(*view_distance);
+            ChunkAnchor {
+                center: WorldPos(tfr.translation).chunk_key().0,
+                radius: view.radius,
+                height: view.height,
+            }
+        })
+        .collect();
+    if anchors == chunk_anchors.anchors && !neighbour_offest.is_changed() {
+        return;
+    }
+    chunk_anchors.anchors = anchors;
+    chunk_anchors.rebuild(&neighbour_offest, &view_distance);
+}
+
+#[test]
+fn test_chunk_anchors() {
+    let view_distance = ViewDistance::new(32.0, 32.0);
+    let neighbour_offest = generate_offset_resoure(32.0, 32.0);
+    let mut chunk_anchors = ChunkAnchors {
+        anchors: vec![
+            ChunkAnchor {
+                center: IVec3::ZERO,
+                radius: 32.0,
+                height: 32.0,
+            },
+            ChunkAnchor {
+                center: IVec3::new(100, 0, 0),
+                radius: 64.0,
+                height: 32.0,
+            },
+        ],
+        ..Default::default()
+    };
+    chunk_anchors.rebuild(&neighbour_offest, &view_distance);
+    assert!(chunk_anchors.contains(ChunkKey(IVec3::new(2, 0, 0)), 0));
+    assert!(chunk_anchors.contains(ChunkKey(IVec3::new(104, 0, 0)), 0));
+    assert!(!chunk_anchors.contains(ChunkKey(IVec3::new(50, 0, 0)), 0));
+    assert!(chunk_anchors.keys.contains(&ChunkKey(IVec3::new(96, 2, 0))));
+    assert!(chunk_anchors
+        .load_keys
+        .contains(&ChunkKey(IVec3::new(3, 0, 0))));
+    assert!(!chunk_anchors.keys.contains(&ChunkKey(IVec3::new(3, 0, 0))));
+}
@@ -0,0 +1,604 @@
+// chunk 存储的后端
+// sled 数据库 / 内存 / region 文件 三种实现 上层只通过 ChunkStore 访问
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::{self, Read, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bevy::prelude::IVec3;
+use sled::{Db, Tree};
+
+use crate::{chunk::ChunkKey, SmallKeyHashMap};
+
+pub type ChunkIter<'a> = Box<dyn Iterator<Item = io::Result<(ChunkKey, Vec<u8>)>> + 'a>;
+
+pub trait ChunkStore: Send + Sync {
+    fn get(&self, chunk_key: ChunkKey) -> io::Result<Option<Vec<u8>>>;
+
+    fn put(&self, chunk_key: ChunkKey, data: &[u8]) -> io::Result<()>;
+
+    fn delete(&self, chunk_key: ChunkKey) -> io::Result<()>;
+
+    // 损坏的数据移到隔离区 并从正常数据中删除
+    fn quarantine(&self, chunk_key: ChunkKey, data: &[u8]) -> io::Result<()>;
+
+    // 遍历全部保存的 chunk
+    fn iter(&self) -> io::Result<ChunkIter<'_>>;
+
+    fn flush(&self) -> io::Result<()>;
+
+    // 世界的元数据 和 chunk 存放在一起
+    fn read_meta(&self) -> io::Result<Option<Vec<u8>>>;
+
+    fn write_meta(&self, data: &[u8]) -> io::Result<()>;
+
+    // 把老版本 hash key 下的数据迁移到新的 key 返回无法迁移的个数 只有 sled 有老数据
+    fn migrate_legacy_keys(&self) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreBackend {
+    Sled,
+    Memory,
+    Region,
+}
+
+impl FromStr for StoreBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sled" => Ok(StoreBackend::Sled),
+            "memory" => Ok(StoreBackend::Memory),
+            "region" => Ok(StoreBackend::Region),
+            _ => Err(format!("unknown backend [{}], use sled/memory/region", s)),
+        }
+    }
+}
+
+// 已经存在的世界按目录内容识别后端 新世界使用 backend
+pub fn open_store(path: &str, backend: StoreBackend) -> io::Result<Arc<dyn ChunkStore>> {
+    let dir = Path::new(path);
+    let backend = if dir.join(REGION_DIR).is_dir() {
+        StoreBackend::Region
+    } else if dir.join("conf").is_file() {
+        StoreBackend::Sled
+    } else {
+        backend
+    };
+    Ok(match backend {
+        StoreBackend::Sled => Arc::new(SledChunkStore::open(path)?),
+        StoreBackend::Memory => Arc::new(MemoryChunkStore::default()),
+        StoreBackend::Region => Arc::new(RegionChunkStore::open(path)?),
+    })
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// 先写临时文件再替换 写到一半时崩溃也不会留下不完整的文件
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(tmp_path, path)
+}
+
+const META_TREE: &str = "meta";
+const META_KEY: &str = "world";
+const QUARANTINE_TREE: &str = "quarantine";
+// 老版本只加载这些高度的 chunk 水平方向从原点向外查找的最大距离 单位是 chunk
+const LEGACY_Y_RANGE: RangeInclusive<i32> = -7..=8;
+const LEGACY_SEARCH_RADIUS: i32 = 512;
+type LegacyKey = [u8; 8];
+// 找到坐标的老 key 和找不到的老 key
+type ResolvedLegacyKeys = (Vec<(ChunkKey, LegacyKey)>, Vec<LegacyKey>);
+
+pub struct SledChunkStore {
+    db: Db,
+    meta: Tree,
+    quarantine: Tree,
+}
+
+impl SledChunkStore {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let db = sled::open(path)?;
+        let meta = db.open_tree(META_TREE)?;
+        let quarantine = db.open_tree(QUARANTINE_TREE)?;
+        Ok(Self {
+            db,
+            meta,
+            quarantine,
+        })
+    }
+
+    // 老版本的 hash key 无法直接还原坐标 从原点向外一圈一圈计算 hash 找到对应的坐标
+    // 返回找到的坐标和老的 key 以及范围内找不到的老 key
+    fn resolve_legacy_keys(&self) -> io::Result<ResolvedLegacyKeys> {
+        let mut legacy: HashSet<LegacyKey> = HashSet::new();
+        for key in self.db.iter().keys() {
+            if let Ok(key) = LegacyKey::try_from(key?.as_ref()) {
+                legacy.insert(key);
+            }
+        }
+        let mut resolved = Vec::new();
+        let mut radius = 0;
+        while !legacy.is_empty() && radius <= LEGACY_SEARCH_RADIUS {
+            for (x, z) in square_ring(radius) {
+                for y in LEGACY_Y_RANGE {
+                    let chunk_key = ChunkKey(IVec3::new(x, y, z));
+                    let legacy_key = chunk_key.as_legacy_u8_array();
+                    if legacy.remove(&legacy_key) {
+                        resolved.push((chunk_key, legacy_key));
+                    }
+                }
+            }
+            radius += 1;
+        }
+        Ok((resolved, legacy.into_iter().collect()))
+    }
+}
+
+// 正方形边上的坐标
+fn square_ring(radius: i32) -> Vec<(i32, i32)> {
+    if radius == 0 {
+        return vec![(0, 0)];
+    }
+    let mut result = Vec::with_capacity(radius as usize * 8);
+    for i in -radius..=radius {
+        result.push((i, -radius));
+        result.push((i, radius));
+    }
+    for i in -radius + 1..radius {
+        result.push((-radius, i));
+        result.push((radius, i));
+    }
+    result
+}
+
+impl ChunkStore for SledChunkStore {
+    // 先用新的 key 读取 没有的话尝试老版本 hash key 读到后迁移到新的 key 下
+    fn get(&self, chunk_key: ChunkKey) -> io::Result<Option<Vec<u8>>> {
+        if let Some(data) = self.db.get(chunk_key.as_u8_array())? {
+            return Ok(Some(data.to_vec()));
+        }
+        let legacy_key = chunk_key.as_legacy_u8_array();
+        match self.db.get(legacy_key)? {
+            Some(data) => {
+                self.db.insert(chunk_key.as_u8_array(), data.clone())?;
+                self.db.remove(legacy_key)?;
+                Ok(Some(data.to_vec()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, chunk_key: ChunkKey, data: &[u8]) -> io::Result<()> {
+        self.db.insert(chunk_key.as_u8_array(), data)?;
+        Ok(())
+    }
+
+    fn delete(&self, chunk_key: ChunkKey) -> io::Result<()> {
+        self.db.remove(chunk_key.as_u8_array())?;
+        self.db.remove(chunk_key.as_legacy_u8_array())?;
+        Ok(())
+    }
+
+    fn quarantine(&self, chunk_key: ChunkKey, data: &[u8]) -> io::Result<()> {
+        self.quarantine.insert(chunk_key.as_u8_array(), data)?;
+        self.delete(chunk_key)
+    }
+
+    // 还没有迁移的老 key 还原出坐标后一起遍历 找不到坐标的作为读取错误返回
+    fn iter(&self) -> io::Result<ChunkIter<'_>> {
+        let (resolved, unresolved) = self.resolve_legacy_keys()?;
+        let current = self.db.iter().filter_map(|item| match item {
+            Ok((key, value)) => {
+                ChunkKey::from_u8_array(&key).map(|chunk_key| Ok((chunk_key, value.to_vec())))
+            }
+            Err(e) => Some(Err(e.into())),
+        });
+        // 新的 key 下已经有数据时 老的数据不会再被读取
+        let legacy = resolved
+            .into_iter()
+            .filter_map(move |(chunk_key, legacy_key)| {
+                match self.db.contains_key(chunk_key.as_u8_array()) {
+                    Ok(true) => None,
+                    Ok(false) => self
+                        .db
+                        .get(legacy_key)
+                        .map(|data| data.map(|data| (chunk_key, data.to_vec())))
+                        .map_err(io::Error::from)
+                        .transpose(),
+                    Err(e) => Some(Err(e.into())),
+                }
+            });
+        let lost = unresolved.into_iter().map(|legacy_key| {
+            Err(invalid_data(format!(
+                "cannot find the position of legacy chunk key {:?}",
+                legacy_key
+            )))
+        });
+        Ok(Box::new(current.chain(legacy).chain(lost)))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn read_meta(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.meta.get(META_KEY)?.map(|data| data.to_vec()))
+    }
+
+    fn write_meta(&self, data: &[u8]) -> io::Result<()> {
+        self.meta.insert(META_KEY, data)?;
+        Ok(())
+    }
+
+    // 先写入新的 key 再删除老的 中途退出时下次打开会继续迁移
+    fn migrate_legacy_keys(&self) -> io::Result<usize> {
+        let (resolved, unresolved) = self.resolve_legacy_keys()?;
+        for (chunk_key, legacy_key) in resolved {
+            if let Some(data) = self.db.get(legacy_key)? {
+                if !self.db.contains_key(chunk_key.as_u8_array())? {
+                    self.db.insert(chunk_key.as_u8_array(), data)?;
+                }
+            }
+            self.db.remove(legacy_key)?;
+        }
+        self.db.flush()?;
+        Ok(unresolved.len())
+    }
+}
+
+// 只在内存中的存储 测试和不需要保存的运行使用
+#[derive(Default)]
+pub struct MemoryChunkStore {
+    chunks: Mutex<SmallKeyHashMap<ChunkKey, Vec<u8>>>,
+    quarantine: Mutex<SmallKeyHashMap<ChunkKey, Vec<u8>>>,
+    meta: Mutex<Option<Vec<u8>>>,
+}
+
+impl ChunkStore for MemoryChunkStore {
+    fn get(&self, chunk_key: ChunkKey) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.chunks.lock().unwrap().get(&chunk_key).cloned())
+    }
+
+    fn put(&self, chunk_key: ChunkKey, data: &[u8]) -> io::Result<()> {
+        self.chunks.lock().unwrap().insert(chunk_key, data.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, chunk_key: ChunkKey) -> io::Result<()> {
+        self.chunks.lock().unwrap().remove(&chunk_key);
+        Ok(())
+    }
+
+    fn quarantine(&self, chunk_key: ChunkKey, data: &[u8]) -> io::Result<()> {
+        self.quarantine
+            .lock()
+            .unwrap()
+            .insert(chunk_key, data.to_vec());
+        self.delete(chunk_key)
+    }
+
+    fn iter(&self) -> io::Result<ChunkIter<'_>> {
+        let items: Vec<_> = self
+            .chunks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, data)| Ok((*key, data.clone())))
+            .collect();
+        Ok(Box::new(items.into_iter()))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn read_meta(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.meta.lock().unwrap().clone())
+    }
+
+    fn write_meta(&self, data: &[u8]) -> io::Result<()> {
+        *self.meta.lock().unwrap() = Some(data.to_vec());
+        Ok(())
+    }
+}
+
+// region 文件 每个文件保存 REGION_SIZE x REGION_SIZE 列的全部 chunk
+pub const REGION_SIZE: i32 = 32;
+const REGION_DIR: &str = "region";
+const REGION_META_FILE: &str = "world.meta";
+const REGION_QUARANTINE_DIR: &str = "quarantine";
+const REGION_MAGIC: &[u8; 4] = b"JJRG";
+const REGION_VERSION: u8 = 1;
+// 内存中最多保留的 region 个数 超过后先写回再丢掉最久没用的
+pub const REGION_CACHE_LIMIT: usize = 64;
+
+#[derive(Default)]
+struct Region {
+    chunks: BTreeMap<[i32; 3], Vec<u8>>,
+    dirty: bool,
+    last_used: u64,
+}
+
+pub struct RegionChunkStore {
+    dir: PathBuf,
+    // 已经读取的 region 修改后在 flush 时整体写回
+    regions: Mutex<SmallKeyHashMap<IVec3, Region>>,
+    max_regions: usize,
+    clock: AtomicU64,
+}
+
+impl RegionChunkStore {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::open_with_limit(path, REGION_CACHE_LIMIT)
+    }
+
+    pub fn open_with_limit(path: &str, max_regions: usize) -> io::Result<Self> {
+        let dir = PathBuf::from(path);
+        fs::create_dir_all(dir.join(REGION_DIR))?;
+        Ok(Self {
+            dir,
+            regions: Mutex::new(SmallKeyHashMap::new()),
+            max_regions: max_regions.max(1),
+            clock: AtomicU64::new(0),
+        })
+    }
+
+    fn region_key(chunk_key: ChunkKey) -> IVec3 {
+        IVec3::new(
+            chunk_key.0.x.div_euclid(REGION_SIZE),
+            0,
+            chunk_key.0.z.div_euclid(REGION_SIZE),
+        )
+    }
+
+    fn region_path(&self, region_key: IVec3) -> PathBuf {
+        self.dir
+            .join(REGION_DIR)
+            .join(format!("r.{}.{}.bin", region_key.x, region_key.z))
+    }
+
+    fn parse_region_name(name: &str) -> Option<IVec3> {
+        let mut parts = name.strip_prefix("r.")?.strip_suffix(".bin")?.split('.');
+        let x = parts.next()?.parse().ok()?;
+        let z = parts.next()?.parse().ok()?;
+        Some(IVec3::new(x, 0, z))
+    }
+
+    fn read_region(&self, region_key: IVec3) -> io::Result<Region> {
+        let mut file = match fs::File::open(self.region_path(region_key)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Region::default()),
+            Err(e) => return Err(e),
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        if data.len() < 5 || &data[0..4] != REGION_MAGIC || data[4] != REGION_VERSION {
+            return Err(invalid_data(format!("bad region file {:?}", region_key)));
+        }
+        let chunks = bincode::deserialize(&data[5..]).map_err(invalid_data)?;
+        Ok(Region {
+            chunks,
+            ..Default::default()
+        })
+    }
+
+    // 避免写到一半时损坏整个 region
+    fn write_region(&self, region_key: IVec3, region: &Region) -> io::Result<()> {
+        let mut data = Vec::new();
+        data.extend_from_slice(REGION_MAGIC);
+        data.push(REGION_VERSION);
+        data.extend(bincode::serialize(&region.chunks).map_err(invalid_data)?);
+        write_atomic(&self.region_path(region_key), &data)
+    }
+
+    fn with_region<R>(
+        &self,
+        chunk_key: ChunkKey,
+        f: impl FnOnce(&mut Region) -> R,
+    ) -> io::Result<R> {
+        let region_key = Self::region_key(chunk_key);
+        let mut regions = self.regions.lock().unwrap();
+        if !regions.contains_key(&region_key) {
+            self.evict(&mut regions)?;
+            let region = self.read_region(region_key)?;
+            regions.insert(region_key, region);
+        }
+        let region = regions.get_mut(&region_key).unwrap();
+        region.last_used = self.clock.fetch_add(1, Ordering::Relaxed);
+        Ok(f(region))
+    }
+
+    // 给新的 region 腾出位置 修改过的先写回文件
+    fn evict(&self, regions: &mut SmallKeyHashMap<IVec3, Region>) -> io::Result<()> {
+        if regions.len() < self.max_regions {
+            return Ok(());
+        }
+        let mut by_age: Vec<(u64, IVec3)> = regions
+            .iter()
+            .map(|(key, region)| (region.last_used, *key))
+            .collect();
+        by_age.sort_by_key(|(last_used, _)| *last_used);
+        for (_, region_key) in by_age {
+            if regions.len() < self.max_regions {
+                break;
+            }
+            let region = regions.get_mut(&region_key).unwrap();
+            if region.dirty {
+                self.write_region(region_key, region)?;
+                region.dirty = false;
+            }
+            regions.remove(&region_key);
+        }
+        Ok(())
+    }
+}
+
+impl ChunkStore for RegionChunkStore {
+    fn get(&self, chunk_key: ChunkKey) -> io::Result<Option<Vec<u8>>> {
+        self.with_region(chunk_key, |region| {
+            region.chunks.get(&chunk_key.0.to_array()).cloned()
+        })
+    }
+
+    fn put(&self, chunk_key: ChunkKey, data: &[u8]) -> io::Result<()> {
+        self.with_region(chunk_key, |region| {
+            region.chunks.insert(chunk_key.0.to_array(), data.to_vec());
+            region.dirty = true;
+        })
+    }
+
+    fn delete(&self, chunk_key: ChunkKey) -> io::Result<()> {
+        self.with_region(chunk_key, |region| {
+            if region.chunks.remove(&chunk_key.0.to_array()).is_some() {
+                region.dirty = true;
+            }
+        })
+    }
+
+    fn quarantine(&self, chunk_key: ChunkKey, data: &[u8]) -> io::Result<()> {
+        let dir = self.dir.join(REGION_QUARANTINE_DIR);
+        fs::create_dir_all(&dir)?;
+        let [x, y, z] = chunk_key.0.to_array();
+        write_atomic(&dir.join(format!("c.{}.{}.{}.bin", x, y, z)), data)?;
+        self.delete(chunk_key)
+    }
+
+    fn iter(&self) -> io::Result<ChunkIter<'_>> {
+        // 先把内存中修改过的写回 然后逐个读取文件
+        self.flush()?;
+        let mut region_keys = Vec::new();
+        for entry in fs::read_dir(self.dir.join(REGION_DIR))? {
+            let entry = entry?;
+            if let Some(region_key) = entry.file_name().to_str().and_then(Self::parse_region_name) {
+                region_keys.push(region_key);
+            }
+        }
+        Ok(Box::new(region_keys.into_iter().flat_map(
+            move |region_key| -> Vec<io::Result<(ChunkKey, Vec<u8>)>> {
+                match self.read_region(region_key) {
+                    Ok(region) => region
+                        .chunks
+                        .into_iter()
+                        .map(|(key, data)| Ok((ChunkKey(IVec3::from_array(key)), data)))
+                        .collect(),
+                    Err(e) => vec![Err(e)],
+                }
+            },
+        )))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut regions = self.regions.lock().unwrap();
+        for (region_key, region) in regions.iter_mut() {
+            if region.dirty {
+                self.write_region(*region_key, region)?;
+                region.dirty = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_meta(&self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.dir.join(REGION_META_FILE)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // 元数据损坏后整个世界都无法打开
+    fn write_meta(&self, data: &[u8]) -> io::Result<()> {
+        write_atomic(&self.dir.join(REGION_META_FILE), data)
+    }
+}
+
+#[test]
+fn test_chunk_stores() {
+    let dir = std::env::temp_dir().join(format!("just_join_region_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let stores: Vec<Arc<dyn ChunkStore>> = vec![
+        Arc::new(MemoryChunkStore::default()),
+        Arc::new(RegionChunkStore::open(dir.to_str().unwrap()).unwrap()),
+    ];
+    for store in stores {
+        let a = ChunkKey(IVec3::new(-1, 3, 40));
+        let b = ChunkKey(IVec3::new(31, -7, 0));
+        store.put(a, &[1, 2, 3]).unwrap();
+        store.put(b, &[4]).unwrap();
+        assert_eq!(store.get(a).unwrap(), Some(vec![1, 2, 3]));
+        store.delete(a).unwrap();
+        assert_eq!(store.get(a).unwrap(), None);
+        store.flush().unwrap();
+        let all: Vec<_> = store.iter().unwrap().map(|item| item.unwrap()).collect();
+        assert_eq!(all, vec![(b, vec![4])]);
+        store.write_meta(b"meta").unwrap();
+        assert_eq!(store.read_meta().unwrap(), Some(b"meta".to_vec()));
+    }
+
+    // 重新打开后可以读到写入的数据
+    let reopen = RegionChunkStore::open(dir.to_str().unwrap()).unwrap();
+    assert_eq!(
+        reopen.get(ChunkKey(IVec3::new(31, -7, 0))).unwrap(),
+        Some(vec![4])
+    );
+    let _ = fs::remove_dir_all(&dir);
+
+    // 超过缓存上限时 旧的 region 写回文件后从内存中移除
+    let store = RegionChunkStore::open_with_limit(dir.to_str().unwrap(), 2).unwrap();
+    let keys: Vec<ChunkKey> = (0..5)
+        .map(|i| ChunkKey(IVec3::new(i * REGION_SIZE, 0, 0)))
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        store.put(*key, &[i as u8]).unwrap();
+    }
+    assert!(store.regions.lock().unwrap().len() <= 2);
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(store.get(*key).unwrap(), Some(vec![i as u8]));
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_sled_legacy_keys() {
+    let dir = std::env::temp_dir().join(format!("just_join_sled_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let store = SledChunkStore::open(dir.to_str().unwrap()).unwrap();
+    let old = ChunkKey(IVec3::new(-3, 5, 7));
+    let both = ChunkKey(IVec3::new(2, 0, -1));
+    store.db.insert(old.as_legacy_u8_array(), &[1]).unwrap();
+    store.db.insert(both.as_legacy_u8_array(), &[2]).unwrap();
+    store.put(both, &[3]).unwrap();
+
+    // 没有迁移之前遍历也能看到老 key 下的数据
+    let mut all: Vec<_> = store.iter().unwrap().map(|item| item.unwrap()).collect();
+    all.sort_by_key(|(chunk_key, _)| chunk_key.as_u8_array());
+    assert_eq!(all, vec![(old, vec![1]), (both, vec![3])]);
+
+    assert_eq!(store.migrate_legacy_keys().unwrap(), 0);
+    assert!(store.db.iter().keys().all(|key| key.unwrap().len() != 8));
+    assert_eq!(store.get(old).unwrap(), Some(vec![1]));
+    assert_eq!(store.get(both).unwrap(), Some(vec![3]));
+    drop(store);
+    let _ = fs::remove_dir_all(&dir);
+}
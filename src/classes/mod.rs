@@ -22,11 +22,9 @@ pub fn c_text(_a: &AssetServer, b: &mut TextBundle) {
 
 // 字体配置
 pub fn c_pixel(assets: &AssetServer, s: &mut TextStyle) {
-    s.font = assets
-        .load("font/ark-pixel-12px-monospaced-zh_hk.ttf")
-        .into();
+    s.font = assets.load("font/ark-pixel-12px-monospaced-zh_hk.ttf");
     s.font_size = 12.;
-    s.color = Color::BLACK.into();
+    s.color = Color::BLACK;
 }
 
 pub fn c_grid(b: &mut NodeBundle) {
@@ -43,19 +41,22 @@ pub fn c_inv_slot(assets: &AssetServer, b: &mut ImageBundle) {
     b.image = assets.load("ui/item_slot.png").into();
 }
 
+#[allow(dead_code)]
 pub fn c_bule(node: &mut NodeBundle) {
     node.background_color = Color::BLUE.into();
 }
 
+#[allow(dead_code)]
 pub fn c_red(node: &mut NodeBundle) {
     node.background_color = Color::RED.into();
 }
 
+#[allow(dead_code)]
 pub fn c_yellow(node: &mut NodeBundle) {
     node.background_color = Color::YELLOW.into();
 }
 
-pub fn c_toolbar_box_normal(assets: &AssetServer, node: &mut ButtonBundle) {
+pub fn c_toolbar_box_normal(_assets: &AssetServer, node: &mut ButtonBundle) {
     node.style.width = Val::Px(68.);
     node.style.height = Val::Px(68.);
     node.style.border = UiRect::all(Val::Px(2.));
@@ -68,7 +69,7 @@ pub fn c_overflow(node: &mut NodeBundle) {
     node.style.position_type = PositionType::Absolute;
 }
 
-pub fn c_test_staff(assets: &AssetServer, b: &mut ImageBundle) {
+pub fn c_test_staff(_assets: &AssetServer, b: &mut ImageBundle) {
     b.z_index = ZIndex::Global(4);
     // b.style.top = Val::Px(64. - 40.);
     // b.style.left = Val::Px(-64. + 40.);
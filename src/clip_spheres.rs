@@ -1,6 +1,6 @@
-use bevy::prelude::{Component, Query, ResMut, Resource, Transform, Vec3, With};
+use bevy::prelude::{Component, Query, Res, ResMut, Resource, Transform, Vec3, With};
 
-use crate::VIEW_RADIUS;
+use crate::view_distance::ViewDistance;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Sphere3 {
@@ -16,7 +16,8 @@ pub struct ClipSpheres {
 
 pub fn update_clip_shpere_system<T>(
     mut clip_spheres: ResMut<ClipSpheres>,
-    mut query: Query<&mut Transform, With<T>>,
+    query: Query<&mut Transform, With<T>>,
+    view_distance: Res<ViewDistance>,
 ) where
     T: Component,
 {
@@ -29,6 +30,6 @@ pub fn update_clip_shpere_system<T>(
     clip_spheres.old_sphere = clip_spheres.new_sphere;
     clip_spheres.new_sphere = Sphere3 {
         center: position,
-        radius: VIEW_RADIUS,
+        radius: view_distance.radius,
     }
 }
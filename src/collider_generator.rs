@@ -9,15 +9,19 @@ use bevy::{
     tasks::{AsyncComputeTaskPool, Task},
 };
 use bevy_rapier3d::prelude::{Collider, RigidBody};
-use std::collections::HashSet;
 
 use crate::{
-    chunk::{find_chunk_keys_array_by_shpere_y_0, generate_offset_array_with_y_0, ChunkKey},
+    chunk::{find_chunk_keys_array_by_shpere, generate_offset_array, in_cylinder, ChunkKey},
     clip_spheres::ClipSpheres,
+    coordinate::WorldPos,
     mesh_generator::{MeshManager, MeshSystem},
-    SmallKeyHashMap, CHUNK_SIZE,
+    SmallKeyHashMap,
 };
 
+// 只在角色附近生成碰撞体 单位是 chunk
+pub const COLLIDER_RADIUS: f32 = 2.0;
+pub const COLLIDER_HEIGHT: i32 = 2;
+
 #[derive(Debug, Component)]
 pub struct TerrainPhysics;
 
@@ -34,30 +38,30 @@ pub struct ColliderTasksManager {
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum ColliderSystem {
-    COLLIDER_TASK,
-    COLLIDER_SPAWN,
-    COLLIDER_DESPAWN,
+    Task,
+    Spawn,
+    Despawn,
 }
 
 // 通过当前位置更新要显示的物理结构
 pub fn update_collider(
-    mut mesh_manager: ResMut<MeshManager>,
-    mut collider_manager: ResMut<ColliderManager>,
+    mesh_manager: ResMut<MeshManager>,
+    collider_manager: ResMut<ColliderManager>,
     mut collider_tasks: ResMut<ColliderTasksManager>,
     meshes: Res<Assets<Mesh>>,
     clip_spheres: Res<ClipSpheres>,
 ) {
-    for chunk_key in find_chunk_keys_array_by_shpere_y_0(
+    for chunk_key in find_chunk_keys_array_by_shpere(
         clip_spheres.new_sphere,
-        generate_offset_array_with_y_0(2),
+        generate_offset_array(COLLIDER_RADIUS, COLLIDER_HEIGHT),
     )
     .drain(..)
     {
         // 这里的每个key就是要生成的
         let pool = AsyncComputeTaskPool::get();
 
-        if (mesh_manager.mesh_storge.contains_key(&chunk_key)
-            && !collider_manager.entities.contains_key(&chunk_key))
+        if mesh_manager.mesh_storge.contains_key(&chunk_key)
+            && !collider_manager.entities.contains_key(&chunk_key)
         {
             let mesh_handle = mesh_manager.mesh_storge.get(&chunk_key).unwrap();
             if let Some(mesh) = meshes.get(mesh_handle) {
@@ -70,7 +74,7 @@ pub fn update_collider(
                     let indices: Vec<u32> =
                         mesh.indices().unwrap().iter().map(|x| x as u32).collect();
                     let collider_vertices: Vec<Vec3> =
-                        positions.iter().cloned().map(|p| Vec3::from(p)).collect();
+                        positions.iter().cloned().map(Vec3::from).collect();
                     let collider_indices: Vec<[u32; 3]> =
                         indices.chunks(3).map(|i| [i[0], i[1], i[2]]).collect();
                     let collider: Collider = Collider::trimesh(collider_vertices, collider_indices);
@@ -89,24 +93,19 @@ pub fn spawn_collider(
     mut commands: Commands,
 ) {
     for ele in collider_tasks.tasks.drain(..) {
-        match futures_lite::future::block_on(futures_lite::future::poll_once(ele)) {
-            Some((chunk_key, collider)) => {
-                let entity = commands
-                    .spawn((
-                        TerrainPhysics,
-                        Transform::from_xyz(
-                            (chunk_key.0.x * CHUNK_SIZE) as f32 - CHUNK_SIZE as f32 / 2.0 - 1.0,
-                            -128.0 + CHUNK_SIZE as f32 / 2.0,
-                            (chunk_key.0.z * CHUNK_SIZE) as f32 - CHUNK_SIZE as f32 / 2.0 - 1.0,
-                        ),
-                        GlobalTransform::default(),
-                    ))
-                    .insert(RigidBody::Fixed)
-                    .insert(collider)
-                    .id();
-                collider_manager.entities.insert(chunk_key, entity);
-            }
-            None => {}
+        if let Some((chunk_key, collider)) =
+            futures_lite::future::block_on(futures_lite::future::poll_once(ele))
+        {
+            let entity = commands
+                .spawn((
+                    TerrainPhysics,
+                    Transform::from_translation(chunk_key.mesh_origin()),
+                    GlobalTransform::default(),
+                ))
+                .insert(RigidBody::Fixed)
+                .insert(collider)
+                .id();
+            collider_manager.entities.insert(chunk_key, entity);
         }
     }
 }
@@ -116,21 +115,13 @@ pub fn despawn_collider(
     mut collider_manager: ResMut<ColliderManager>,
     mut commands: Commands,
 ) {
-    let neighbour_offest = generate_offset_array_with_y_0(2);
-    let mut chunks_to_remove = HashSet::new();
-    for key in
-        find_chunk_keys_array_by_shpere_y_0(clip_spheres.old_sphere, neighbour_offest.clone())
-            .drain(..)
-    {
-        chunks_to_remove.insert(key);
-    }
-
-    for key in
-        find_chunk_keys_array_by_shpere_y_0(clip_spheres.new_sphere, neighbour_offest.clone())
-            .drain(..)
-    {
-        chunks_to_remove.remove(&key);
-    }
+    let center = WorldPos(clip_spheres.new_sphere.center).chunk_key().0;
+    let chunks_to_remove: Vec<ChunkKey> = collider_manager
+        .entities
+        .keys()
+        .filter(|key| !in_cylinder(key.0 - center, COLLIDER_RADIUS, COLLIDER_HEIGHT))
+        .cloned()
+        .collect();
 
     for chunk_key in chunks_to_remove.into_iter() {
         if let Some(entity) = collider_manager.entities.remove(&chunk_key) {
@@ -151,18 +142,15 @@ impl Plugin for TerrainPhysicsPlugin {
         .add_systems(
             Update,
             update_collider
-                .in_set(ColliderSystem::COLLIDER_TASK)
-                .after(MeshSystem::UPDATE_MESH),
+                .in_set(ColliderSystem::Task)
+                .after(MeshSystem::UpdateMesh),
         )
         .add_systems(
             Update,
             spawn_collider
-                .in_set(ColliderSystem::COLLIDER_SPAWN)
-                .after(ColliderSystem::COLLIDER_TASK),
+                .in_set(ColliderSystem::Spawn)
+                .after(ColliderSystem::Task),
         )
-        .add_systems(
-            Last,
-            despawn_collider.in_set(ColliderSystem::COLLIDER_SPAWN),
-        );
+        .add_systems(Last, despawn_collider.in_set(ColliderSystem::Despawn));
     }
 }
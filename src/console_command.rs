@@ -1,7 +1,7 @@
 // 测试使用命令
 
 use bevy::prelude::{
-    App, Component, EventReader, IntoSystemConfigs, Plugin, PreUpdate, Query, Res, ResMut,
+    App, Component, EventReader, IVec3, IntoSystemConfigs, Plugin, PreUpdate, Query, Res, ResMut,
     Transform, Update, Vec3, With,
 };
 use bevy_console::{
@@ -12,11 +12,16 @@ use clap::Parser;
 use controller::controller::ControllerFlag;
 
 use crate::{
+    chunk::ChunkKey,
     clip_spheres::{ClipSpheres, Sphere3},
+    coordinate::WorldPos,
+    map_database::MapDataBase,
     player_controller::PlayerMe,
     player_ui::ToolbarContent,
     staff::StaffInfoStroge,
-    VIEW_RADIUS,
+    view_distance::{GameSettings, ViewDistance, SETTINGS_FILE},
+    voxel_world::VoxelWorld,
+    world_save::AutoSaveState,
 };
 
 pub struct ConsoleCommandPlugins;
@@ -27,7 +32,9 @@ impl Plugin for ConsoleCommandPlugins {
             .add_systems(PreUpdate, sync_flags)
             .add_systems(Update, raw_commands.in_set(ConsoleSet::Commands))
             .add_console_command::<TpCommand, _>(tp_commands::<PlayerMe>)
-            .add_console_command::<LoadToolbarCommand, _>(load_toolbar_commands);
+            .add_console_command::<LoadToolbarCommand, _>(load_toolbar_commands)
+            .add_console_command::<ResetChunkCommand, _>(reset_chunk_commands::<PlayerMe>)
+            .add_console_command::<ViewDistanceCommand, _>(view_distance_commands);
     }
 }
 
@@ -68,7 +75,7 @@ fn tp_commands<T>(
                     clip_spheres.old_sphere = clip_spheres.new_sphere;
                     clip_spheres.new_sphere = Sphere3 {
                         center: Vec3::new(xx, yy, zz),
-                        radius: VIEW_RADIUS,
+                        radius: clip_spheres.new_sphere.radius,
                     };
                     let mut tf = query.get_single_mut().unwrap();
                     tf.translation = Vec3::new(xx, yy, zz);
@@ -103,3 +110,81 @@ pub fn load_toolbar_commands(
         load_bar.ok();
     }
 }
+
+// 把 chunk 恢复成种子生成的地形 不传坐标时使用角色所在的 chunk
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "reset_chunk", about = "reset chunk to generated terrain")]
+struct ResetChunkCommand {
+    /// chunk x
+    x: Option<i32>,
+    /// chunk y
+    y: Option<i32>,
+    /// chunk z
+    z: Option<i32>,
+}
+
+fn reset_chunk_commands<T>(
+    mut log: ConsoleCommand<ResetChunkCommand>,
+    db: Res<MapDataBase>,
+    mut save_state: ResMut<AutoSaveState>,
+    mut voxel_world: VoxelWorld,
+    query: Query<&Transform, With<T>>,
+) where
+    T: Component,
+{
+    if let Some(Ok(ResetChunkCommand { x, y, z })) = log.take() {
+        let chunk_key = match (x, y, z) {
+            (Some(x), Some(y), Some(z)) => ChunkKey(IVec3::new(x, y, z)),
+            (None, None, None) => match query.get_single() {
+                Ok(tf) => WorldPos(tf.translation).chunk_key(),
+                Err(_) => {
+                    log.failed();
+                    return;
+                }
+            },
+            _ => {
+                log.failed();
+                return;
+            }
+        };
+        // 正在后台写入的旧数据可能在删除之后才写完 先等它结束
+        save_state.wait_for_saves();
+        save_state.pending.remove(&chunk_key);
+        // 自己和六个方向的 mesh 都会更新
+        voxel_world.replace_chunk(chunk_key, db.reset_chunk(chunk_key));
+        log.reply(format!("reset chunk {:?}", chunk_key.0));
+        log.ok();
+    }
+}
+
+// 修改视野距离 并保存到设置文件 不传参数时显示当前的距离
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "view_distance", about = "show or change view distance")]
+struct ViewDistanceCommand {
+    /// horizontal radius in blocks
+    radius: Option<f32>,
+    /// vertical distance in blocks
+    height: Option<f32>,
+}
+
+fn view_distance_commands(
+    mut log: ConsoleCommand<ViewDistanceCommand>,
+    mut view_distance: ResMut<ViewDistance>,
+) {
+    if let Some(Ok(ViewDistanceCommand { radius, height })) = log.take() {
+        if let Some(radius) = radius {
+            *view_distance = ViewDistance::new(radius, height.unwrap_or(view_distance.height));
+            let mut settings = GameSettings::read_file(SETTINGS_FILE);
+            settings.view_radius = view_distance.radius;
+            settings.view_height = view_distance.height;
+            if let Err(e) = settings.write_file(SETTINGS_FILE) {
+                log.reply(format!("wrong, to save settings: {}", e));
+            }
+        }
+        log.reply(format!(
+            "view distance {} height {}",
+            view_distance.radius, view_distance.height
+        ));
+        log.ok();
+    }
+}
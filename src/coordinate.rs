@@ -0,0 +1,168 @@
+// 坐标转换
+// chunk 是以 ChunkKey * CHUNK_SIZE 为中心的 key 为 k 的 chunk 包含的体素是 [16k - 8, 16k + 8)
+// 体素 v 占据的空间是 [v, v + 1) 中心在 v + 0.5
+use bevy::prelude::{IVec3, UVec3, Vec3};
+use ndshape::{ConstShape, ConstShape3u32};
+
+use crate::{chunk::ChunkKey, CHUNK_SIZE, CHUNK_SIZE_U32};
+
+pub type ChunkShape = ConstShape3u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
+
+const HALF_CHUNK: i32 = CHUNK_SIZE / 2;
+
+// 世界中的任意位置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldPos(pub Vec3);
+
+// 体素的整数坐标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoxelPos(pub IVec3);
+
+// chunk 内部的坐标 每个轴都在 0..CHUNK_SIZE
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LocalPos(pub UVec3);
+
+impl WorldPos {
+    // 位置所在的体素
+    pub fn voxel(self) -> VoxelPos {
+        VoxelPos(self.0.floor().as_ivec3())
+    }
+
+    pub fn chunk_key(self) -> ChunkKey {
+        self.voxel().chunk_key()
+    }
+}
+
+impl VoxelPos {
+    // 射线命中面上的点 按法向量找到被命中的体素
+    pub fn from_hit(point: Vec3, normal: Vec3) -> Self {
+        WorldPos(point - normal * 0.5).voxel()
+    }
+
+    pub fn from_local(chunk_key: ChunkKey, local: LocalPos) -> Self {
+        VoxelPos(chunk_key.min_voxel().0 + local.0.as_ivec3())
+    }
+
+    pub fn chunk_key(self) -> ChunkKey {
+        let v = self.0 + IVec3::splat(HALF_CHUNK);
+        ChunkKey(IVec3::new(
+            v.x.div_euclid(CHUNK_SIZE),
+            v.y.div_euclid(CHUNK_SIZE),
+            v.z.div_euclid(CHUNK_SIZE),
+        ))
+    }
+
+    pub fn local(self) -> LocalPos {
+        let v = self.0 + IVec3::splat(HALF_CHUNK);
+        LocalPos(UVec3::new(
+            v.x.rem_euclid(CHUNK_SIZE) as u32,
+            v.y.rem_euclid(CHUNK_SIZE) as u32,
+            v.z.rem_euclid(CHUNK_SIZE) as u32,
+        ))
+    }
+
+    #[allow(dead_code)]
+    pub fn split(self) -> (ChunkKey, LocalPos) {
+        (self.chunk_key(), self.local())
+    }
+
+    pub fn center(self) -> WorldPos {
+        WorldPos(self.0.as_vec3() + Vec3::splat(0.5))
+    }
+
+    // 地形生成器使用的坐标 生成器把 chunk 的第一个方块放在 ChunkKey * CHUNK_SIZE
+    pub fn generator(self) -> IVec3 {
+        self.0 + IVec3::splat(HALF_CHUNK)
+    }
+}
+
+impl LocalPos {
+    pub fn from_index(index: usize) -> Self {
+        LocalPos(UVec3::from_array(ChunkShape::delinearize(index as u32)))
+    }
+
+    // 在 chunk 数据中的下标
+    pub fn index(self) -> usize {
+        ChunkShape::linearize(self.0.to_array()) as usize
+    }
+
+    pub fn to_array(self) -> [u32; 3] {
+        self.0.to_array()
+    }
+}
+
+impl ChunkKey {
+    // chunk 中最小的体素
+    pub fn min_voxel(self) -> VoxelPos {
+        VoxelPos(self.0 * CHUNK_SIZE - IVec3::splat(HALF_CHUNK))
+    }
+
+    pub fn center(self) -> WorldPos {
+        WorldPos((self.0 * CHUNK_SIZE).as_vec3())
+    }
+
+    // mesh 和碰撞体的原点 数据四周有一格的邻居 所以要再减一
+    pub fn mesh_origin(self) -> Vec3 {
+        self.min_voxel().0.as_vec3() - Vec3::ONE
+    }
+}
+
+#[test]
+fn test_coordinate() {
+    // 原来 get_chunk_key_axis 的例子
+    assert_eq!(
+        WorldPos(Vec3::new(2.5, 24.5, 0.5)).chunk_key(),
+        ChunkKey(IVec3::new(0, 2, 0))
+    );
+    assert_eq!(WorldPos(Vec3::splat(8.0)).chunk_key(), ChunkKey(IVec3::ONE));
+    assert_eq!(
+        WorldPos(Vec3::splat(7.99)).chunk_key(),
+        ChunkKey(IVec3::ZERO)
+    );
+    assert_eq!(
+        WorldPos(Vec3::splat(-8.0)).chunk_key(),
+        ChunkKey(IVec3::ZERO)
+    );
+    assert_eq!(
+        WorldPos(Vec3::splat(-8.01)).chunk_key(),
+        ChunkKey(IVec3::NEG_ONE)
+    );
+    assert_eq!(
+        WorldPos(Vec3::splat(-24.0)).chunk_key(),
+        ChunkKey(IVec3::NEG_ONE)
+    );
+
+    // 体素和 chunk + 局部坐标互相转换
+    for v in -40..40 {
+        let voxel = VoxelPos(IVec3::new(v, -v * 3, v * 7));
+        let (chunk_key, local) = voxel.split();
+        assert!(local.0.max_element() < CHUNK_SIZE_U32);
+        assert_eq!(VoxelPos::from_local(chunk_key, local), voxel);
+        assert_eq!(LocalPos::from_index(local.index()), local);
+        assert_eq!(voxel.center().voxel(), voxel);
+        assert_eq!(voxel.center().chunk_key(), chunk_key);
+    }
+    assert_eq!(
+        VoxelPos(IVec3::new(-8, 7, 8)).split(),
+        (
+            ChunkKey(IVec3::new(0, 0, 1)),
+            LocalPos(UVec3::new(0, 15, 0))
+        )
+    );
+
+    // 射线命中面时 两边的体素
+    let hit = Vec3::new(5.0, 3.2, -0.7);
+    assert_eq!(
+        VoxelPos::from_hit(hit, Vec3::X),
+        VoxelPos(IVec3::new(4, 3, -1))
+    );
+    assert_eq!(
+        VoxelPos::from_hit(hit, -Vec3::X),
+        VoxelPos(IVec3::new(5, 3, -1))
+    );
+
+    assert_eq!(
+        ChunkKey(IVec3::new(1, 0, -1)).mesh_origin(),
+        Vec3::new(7.0, -9.0, -25.0)
+    );
+}
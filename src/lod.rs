@@ -0,0 +1,450 @@
+// 视野外远处的地形 使用降低精度的数据生成 mesh
+// 第 level 层的一个 LOD 包含 2^level 个 chunk 宽 数据和 mesh 和普通 chunk 一样大
+// 第 0 层只在视野边缘补齐单个 chunk 靠近后由完整的 chunk 替换
+use std::collections::HashSet;
+
+use bevy::{
+    prelude::{
+        AlphaMode, App, Assets, Color, Commands, Entity, Handle, IVec3, IntoSystemConfigs,
+        MaterialMeshBundle, Mesh, Plugin, Res, ResMut, Resource, StandardMaterial, Transform,
+        Update, Vec3,
+    },
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use ndshape::ConstShape;
+
+use crate::{
+    chunk::ChunkKey,
+    chunk_loader::{ChunkAnchor, ChunkAnchors},
+    clip_spheres::ClipSpheres,
+    coordinate::{VoxelPos, WorldPos},
+    map_database::MapDataBase,
+    map_generator::{gen_lod_data_by_seed, TERRAIN_MAX_Y, TERRAIN_MIN_Y},
+    mesh::{gen_mesh, gen_mesh_water, pick_water, PaddedChunkShape},
+    mesh_generator::MeshManager,
+    mesh_material::MaterialStorge,
+    view_distance::ViewDistance,
+    voxel::Voxel,
+    voxel_config::MaterailConfiguration,
+    SmallKeyHashMap, CHUNK_SIZE_ADD_2_U32,
+};
+
+// 最粗的一层 8 倍
+pub const LOD_LEVELS: u32 = 3;
+// 同时在后台生成的 LOD 个数
+pub const MAX_LOD_TASKS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LodKey {
+    pub level: u32,
+    pub key: IVec3,
+}
+
+impl LodKey {
+    // 每个格子代表的方块个数
+    pub fn scale(&self) -> i32 {
+        1 << self.level
+    }
+
+    // 包含的第一个 chunk
+    pub fn min_chunk(&self) -> IVec3 {
+        self.key * self.scale()
+    }
+
+    #[allow(dead_code)]
+    pub fn contains_chunk(&self, chunk_key: ChunkKey) -> bool {
+        let offset = chunk_key.0 - self.min_chunk();
+        offset.min_element() >= 0 && offset.max_element() < self.scale()
+    }
+
+    pub fn overlaps(&self, other: &LodKey) -> bool {
+        let (a_min, b_min) = (self.min_chunk(), other.min_chunk());
+        let (a_max, b_max) = (
+            a_min + IVec3::splat(self.scale()),
+            b_min + IVec3::splat(other.scale()),
+        );
+        a_min.cmplt(b_max).all() && b_min.cmplt(a_max).all()
+    }
+
+    // 包含的 chunk 中离 center 最近的偏移 每个轴分别取最近的
+    pub fn nearest_offset(&self, center: IVec3) -> IVec3 {
+        let min = self.min_chunk() - center;
+        IVec3::ZERO.clamp(min, min + IVec3::splat(self.scale() - 1))
+    }
+
+    // 有 chunk 在锚点的视野内 和加载 chunk 时的判断一致
+    pub fn intersects_view(&self, anchor: &ChunkAnchor) -> bool {
+        anchor
+            .view()
+            .contains(self.nearest_offset(anchor.center), 0)
+    }
+
+    // 下一层的八个 LOD 正好铺满这一个
+    pub fn children(&self) -> impl Iterator<Item = LodKey> {
+        let (level, min) = (self.level - 1, self.key * 2);
+        (0..8).map(move |i| LodKey {
+            level,
+            key: min + IVec3::new(i & 1, i >> 1 & 1, i >> 2),
+        })
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkKey> {
+        let min = self.min_chunk();
+        let scale = self.scale();
+        (0..scale * scale * scale).map(move |i| {
+            ChunkKey(min + IVec3::new(i % scale, i / scale % scale, i / (scale * scale)))
+        })
+    }
+
+    pub fn mesh_origin(&self) -> Vec3 {
+        let min_voxel = ChunkKey(self.min_chunk()).min_voxel().0;
+        (min_voxel - IVec3::splat(self.scale())).as_vec3()
+    }
+
+    // 第一个格子 (包括四周的一格) 的采样点 在格子的中间
+    pub fn sample_origin(&self) -> Vec3 {
+        let min_voxel = ChunkKey(self.min_chunk()).min_voxel().0;
+        let first = min_voxel - IVec3::splat(self.scale()) + IVec3::splat(self.scale() / 2);
+        VoxelPos(first).generator().as_vec3()
+    }
+
+    // 水平方向上 包含的 chunk 离 center 最近和最远的距离 单位是 chunk
+    pub fn horizontal_range(&self, center: IVec3) -> (f32, f32) {
+        let min = self.min_chunk() - center;
+        let max = min + IVec3::splat(self.scale() - 1);
+        let near = self.nearest_offset(center);
+        let far = min.abs().max(max.abs());
+        (
+            ((near.x * near.x + near.z * near.z) as f32).sqrt(),
+            ((far.x * far.x + far.z * far.z) as f32).sqrt(),
+        )
+    }
+}
+
+// 从最粗的一层开始 离得近的 LOD 拆成下一层的八个 所以不同层之间不会重叠
+// 第 level 层的 LOD 有一部分在 chunk_radius * 2^(level-1) 内时拆开
+// 和锚点加载的范围有重叠时一直拆到单个 chunk 视野内的 chunk 只由完整的 mesh 显示
+// 高度覆盖地表可能出现的范围 锚点上下没有加载的部分也由 LOD 显示
+pub fn wanted_lod_keys(
+    center: IVec3,
+    chunk_radius: f32,
+    anchors: &[ChunkAnchor],
+) -> HashSet<LodKey> {
+    let min_y = WorldPos(Vec3::new(0.0, TERRAIN_MIN_Y, 0.0)).chunk_key().0.y;
+    let max_y = WorldPos(Vec3::new(0.0, TERRAIN_MAX_Y, 0.0)).chunk_key().0.y;
+    let scale = 1 << LOD_LEVELS;
+    let outer = chunk_radius * scale as f32;
+    let reach = outer.ceil() as i32;
+    let mut result = HashSet::new();
+    for x in (center.x - reach).div_euclid(scale)..=(center.x + reach).div_euclid(scale) {
+        for z in (center.z - reach).div_euclid(scale)..=(center.z + reach).div_euclid(scale) {
+            for y in min_y.div_euclid(scale)..=max_y.div_euclid(scale) {
+                let lod_key = LodKey {
+                    level: LOD_LEVELS,
+                    key: IVec3::new(x, y, z),
+                };
+                if lod_key.horizontal_range(center).0 <= outer {
+                    split_lod_key(lod_key, center, chunk_radius, anchors, &mut result);
+                }
+            }
+        }
+    }
+    result
+}
+
+fn split_lod_key(
+    lod_key: LodKey,
+    center: IVec3,
+    chunk_radius: f32,
+    anchors: &[ChunkAnchor],
+    result: &mut HashSet<LodKey>,
+) {
+    let loaded = anchors.iter().any(|anchor| lod_key.intersects_view(anchor));
+    if lod_key.level == 0 {
+        if !loaded {
+            result.insert(lod_key);
+        }
+        return;
+    }
+    let near = lod_key.horizontal_range(center).0;
+    if loaded || (lod_key.level > 1 && near <= chunk_radius * (lod_key.scale() / 2) as f32) {
+        for child in lod_key.children() {
+            split_lod_key(child, center, chunk_radius, anchors, result);
+        }
+    } else {
+        result.insert(lod_key);
+    }
+}
+
+// 四周多出的一圈当作空气 每个 LOD 的边上都会生成面 像裙边一样封闭
+// 精度不同的邻居或者完整的 chunk 高度对不上时 不会漏出缝隙
+fn with_skirts(mut voxels: Vec<Voxel>) -> Vec<Voxel> {
+    let last = CHUNK_SIZE_ADD_2_U32 - 1;
+    for i in 0..PaddedChunkShape::SIZE {
+        let pos = PaddedChunkShape::delinearize(i);
+        if pos.iter().any(|v| *v == 0 || *v == last) {
+            voxels[i as usize] = Voxel::EMPTY;
+        }
+    }
+    voxels
+}
+
+#[derive(Default, Resource)]
+pub struct LodManager {
+    // 上次计算时相机所在的 chunk 视野和加载的锚点
+    pub center: Option<IVec3>,
+    pub chunk_radius: f32,
+    pub anchors: Vec<ChunkAnchor>,
+    pub wanted: HashSet<LodKey>,
+    // 已经生成好的 LOD 没有 mesh 时为空
+    pub entities: SmallKeyHashMap<LodKey, Vec<Entity>>,
+    #[allow(clippy::type_complexity)]
+    pub tasks: SmallKeyHashMap<LodKey, Task<(Option<Mesh>, Option<Mesh>)>>,
+    pub water_material: Option<Handle<StandardMaterial>>,
+}
+
+impl LodManager {
+    // 范围内需要显示的 LOD 和 chunk 都已经生成好了
+    fn covered(
+        &self,
+        lod_key: &LodKey,
+        chunk_anchors: &ChunkAnchors,
+        mesh_manager: &MeshManager,
+    ) -> bool {
+        self.wanted
+            .iter()
+            .filter(|other| other.overlaps(lod_key))
+            .all(|other| self.entities.contains_key(other))
+            && lod_key
+                .chunks()
+                .filter(|chunk_key| chunk_anchors.contains(*chunk_key, 0))
+                .all(|chunk_key| {
+                    mesh_manager.fast_key.contains(&chunk_key)
+                        && !mesh_manager.pending.contains_key(&chunk_key)
+                })
+    }
+}
+
+// 相机移动到其他 chunk 视野或者加载范围改变时 重新计算需要的 LOD
+pub fn update_lod_keys_system(
+    clip_spheres: Res<ClipSpheres>,
+    view_distance: Res<ViewDistance>,
+    chunk_anchors: Res<ChunkAnchors>,
+    mut lod_manager: ResMut<LodManager>,
+) {
+    let center = WorldPos(clip_spheres.new_sphere.center).chunk_key().0;
+    let chunk_radius = view_distance.chunk_radius();
+    if lod_manager.center == Some(center)
+        && lod_manager.chunk_radius == chunk_radius
+        && lod_manager.anchors == chunk_anchors.anchors
+    {
+        return;
+    }
+    let lod_manager = lod_manager.as_mut();
+    lod_manager.center = Some(center);
+    lod_manager.chunk_radius = chunk_radius;
+    lod_manager.anchors = chunk_anchors.anchors.clone();
+    lod_manager.wanted = wanted_lod_keys(center, chunk_radius, &chunk_anchors.anchors);
+    // 不再需要的任务直接丢弃
+    let wanted = &lod_manager.wanted;
+    lod_manager.tasks.retain(|key, _| wanted.contains(key));
+}
+
+// 近的先生成
+pub fn gen_lod_mesh_system(
+    mut lod_manager: ResMut<LodManager>,
+    db: Res<MapDataBase>,
+    material_config: Res<MaterailConfiguration>,
+) {
+    let Some(center) = lod_manager.center else {
+        return;
+    };
+    let free = MAX_LOD_TASKS.saturating_sub(lod_manager.tasks.len());
+    if free == 0 {
+        return;
+    }
+    let mut missing: Vec<(f32, LodKey)> = lod_manager
+        .wanted
+        .iter()
+        .filter(|key| {
+            !lod_manager.entities.contains_key(key) && !lod_manager.tasks.contains_key(key)
+        })
+        .map(|key| (key.horizontal_range(center).0, *key))
+        .collect();
+    missing.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let pool = AsyncComputeTaskPool::get();
+    for (_, lod_key) in missing.into_iter().take(free) {
+        let seed = db.meta.seed;
+        let material_config = material_config.clone();
+        let task = pool.spawn(async move {
+            let voxels =
+                gen_lod_data_by_seed(seed, lod_key.sample_origin(), lod_key.scale() as f32);
+            let mesh = gen_mesh(with_skirts(voxels.clone()), material_config.clone());
+            let water_mesh = gen_mesh_water(pick_water(voxels), material_config);
+            (mesh, water_mesh)
+        });
+        lod_manager.tasks.insert(lod_key, task);
+    }
+}
+
+pub fn update_lod_mesh_system(
+    mut commands: Commands,
+    mut lod_manager: ResMut<LodManager>,
+    mut mesh_assets: ResMut<Assets<Mesh>>,
+    materials: Res<MaterialStorge>,
+    mut materials_assets: ResMut<Assets<StandardMaterial>>,
+    chunk_anchors: Res<ChunkAnchors>,
+    mesh_manager: Res<MeshManager>,
+) {
+    let mut finished = Vec::new();
+    lod_manager.tasks.retain(|key, task| {
+        match futures_lite::future::block_on(futures_lite::future::poll_once(task)) {
+            Some(result) => {
+                finished.push((*key, result));
+                false
+            }
+            None => true,
+        }
+    });
+    let water_material = lod_manager
+        .water_material
+        .get_or_insert_with(|| {
+            materials_assets.add(StandardMaterial {
+                base_color: Color::rgba(10. / 255., 18. / 255., 246. / 255., 0.6),
+                alpha_mode: AlphaMode::Blend,
+                ..Default::default()
+            })
+        })
+        .clone();
+    for (lod_key, (mesh, water_mesh)) in finished {
+        // 格子放大到对应的方块个数
+        let transform = Transform::from_translation(lod_key.mesh_origin())
+            .with_scale(Vec3::splat(lod_key.scale() as f32));
+        let mut entities = Vec::new();
+        if let Some(mesh) = mesh {
+            entities.push(
+                commands
+                    .spawn(MaterialMeshBundle {
+                        transform,
+                        mesh: mesh_assets.add(mesh),
+                        material: materials.0.clone(),
+                        ..Default::default()
+                    })
+                    .id(),
+            );
+        }
+        if let Some(water_mesh) = water_mesh {
+            entities.push(
+                commands
+                    .spawn(MaterialMeshBundle {
+                        transform,
+                        mesh: mesh_assets.add(water_mesh),
+                        material: water_material.clone(),
+                        ..Default::default()
+                    })
+                    .id(),
+            );
+        }
+        lod_manager.entities.insert(lod_key, entities);
+    }
+
+    // 不再需要的 LOD 等范围内更精细的都生成好后再删除 避免出现空洞
+    let retired: Vec<LodKey> = lod_manager
+        .entities
+        .keys()
+        .filter(|key| {
+            !lod_manager.wanted.contains(key)
+                && lod_manager.covered(key, &chunk_anchors, &mesh_manager)
+        })
+        .cloned()
+        .collect();
+    for lod_key in retired {
+        if let Some(entities) = lod_manager.entities.remove(&lod_key) {
+            for entity in entities {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+pub struct LodPlugin;
+
+impl Plugin for LodPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LodManager>().add_systems(
+            Update,
+            (
+                update_lod_keys_system,
+                gen_lod_mesh_system,
+                update_lod_mesh_system,
+            )
+                .chain(),
+        );
+    }
+}
+
+#[test]
+fn test_lod_keys() {
+    let lod_key = LodKey {
+        level: 2,
+        key: IVec3::new(1, 0, -1),
+    };
+    assert!(lod_key.contains_chunk(ChunkKey(IVec3::new(7, 3, -4))));
+    assert!(!lod_key.contains_chunk(ChunkKey(IVec3::new(8, 3, -4))));
+    assert_eq!(lod_key.chunks().count(), 64);
+    assert!(lod_key
+        .chunks()
+        .all(|chunk_key| lod_key.contains_chunk(chunk_key)));
+    assert_eq!(
+        lod_key.horizontal_range(IVec3::ZERO),
+        (17f32.sqrt(), 65f32.sqrt())
+    );
+    // 和普通 chunk 的 mesh 原点对齐 只是多了一个格子
+    assert_eq!(
+        lod_key.mesh_origin(),
+        ChunkKey(IVec3::new(4, 0, -4)).mesh_origin() + Vec3::ONE - Vec3::splat(4.0)
+    );
+
+    // 加载范围外到最远的范围都正好被一个 LOD 覆盖 加载范围内的 chunk 不会被覆盖
+    let chunk_radius = 4.0;
+    let anchor = ChunkAnchor {
+        center: IVec3::new(0, 1, 0),
+        radius: 64.0,
+        height: 32.0,
+    };
+    let wanted = wanted_lod_keys(IVec3::new(0, 1, 0), chunk_radius, &[anchor]);
+    let mut covered: SmallKeyHashMap<ChunkKey, u32> = SmallKeyHashMap::new();
+    for key in wanted.iter() {
+        for chunk_key in key.chunks() {
+            *covered.entry(chunk_key).or_default() += 1;
+        }
+    }
+    assert!(covered.values().all(|count| *count == 1));
+    assert!(covered
+        .keys()
+        .all(|chunk_key| !anchor.view().contains(chunk_key.0 - anchor.center, 0)));
+    let min_y = WorldPos(Vec3::new(0.0, TERRAIN_MIN_Y, 0.0)).chunk_key().0.y;
+    let max_y = WorldPos(Vec3::new(0.0, TERRAIN_MAX_Y, 0.0)).chunk_key().0.y;
+    for x in -40..=40 {
+        for z in [-33, 0, 7, 20] {
+            let distance = ((x * x + z * z) as f32).sqrt();
+            for y in min_y..=max_y {
+                let chunk_key = ChunkKey(IVec3::new(x, y, z));
+                let loaded = anchor.view().contains(chunk_key.0 - anchor.center, 0);
+                if !loaded && distance <= chunk_radius * 8.0 {
+                    assert!(covered.contains_key(&chunk_key));
+                }
+                // 最远一层的一个 LOD 有 8 个 chunk 宽
+                if distance > chunk_radius * 8.0 + 12.0 {
+                    assert!(!covered.contains_key(&chunk_key));
+                }
+            }
+        }
+    }
+    // 加载范围上下的地表也由 LOD 显示 离得近的使用精细的一层
+    assert!(wanted.contains(&LodKey {
+        level: 1,
+        key: IVec3::new(0, 2, 0)
+    }));
+}
@@ -1,54 +1,71 @@
 use bevy::{
-    pbr::{wireframe::WireframePlugin, DirectionalLightShadowMap, Shadow},
     prelude::{
         bevy_main, AmbientLight, App, AssetServer, Assets, Commands, Component, IntoSystemConfigs,
         Last, MaterialPlugin, Msaa, PointLight, PointLightBundle, PreUpdate, Query, Res, ResMut,
-        Startup, SystemSet, Transform, Update, Vec3, With, Without,
+        Startup, Transform, Update, Vec3, With, Without,
     },
     DefaultPlugins,
 };
 // use bevy_atmosphere::prelude::AtmospherePlugin;
-use bevy_rapier3d::{
-    prelude::{NoUserData, RapierPhysicsPlugin},
-    render::RapierDebugRenderPlugin,
-};
+use bevy_rapier3d::prelude::{NoUserData, RapierPhysicsPlugin};
 use chunk::generate_offset_resoure;
 use chunk_command::ChunkCommandsPlugin;
-use chunk_generator::{chunk_generate_system, ChunkMap};
+use chunk_events::ChunkEventsPlugin;
+use chunk_generator::{
+    chunk_generate_system, chunk_load_merge_system, chunk_unload_system, ChunkLoadTasks,
+    ChunkMap, ChunkMapLimit,
+};
+use chunk_loader::{update_chunk_anchors_system, ChunkAnchors};
+use chunk_store::StoreBackend;
 use clip_spheres::{update_clip_shpere_system, ClipSpheres, Sphere3};
 use collider_generator::TerrainPhysicsPlugin;
 use console_command::ConsoleCommandPlugins;
-use controller::controller::{CameraTag, HeadTag};
-use inspector_egui::inspector_ui;
-use map_database::MapDataBase;
+use controller::{
+    controller::HeadTag,
+    utils::CharacterSettings,
+};
+use coordinate::WorldPos;
+use lod::LodPlugin;
+use map_database::{ChunkStorageMode, MapDataBase};
 use mesh_generator::{
     deleter_mesh_system, gen_mesh_system, update_mesh_system, MeshManager, MeshSystem, MeshTasks,
 };
 
-use bevy_egui::EguiPlugin;
 use mesh_material::{BindlessMaterial, MaterialStorge};
+use occlusion::occlusion_culling_system;
 use player_controller::{PlayerControllerPlugin, PlayerMe};
 use player_ui::PlayerUiPlugin;
 use ray_cast::MyRayCastPlugin;
 use sky::SkyPlugin;
 use staff::StaffInfoPlugin;
 use structopt::StructOpt;
+use view_distance::{apply_view_distance_system, GameSettings, ViewDistance, SETTINGS_FILE};
 use voxel_config::{MaterailConfiguration, VoxelMaterialToolPulgin};
+use world_generate::{generate_world, keys_in_box, keys_in_radius};
+use world_save::{AutoSaveConfig, WorldSavePlugin};
+use world_verify::verify_world;
 // use sky::SkyPlugin;
 
 mod chunk;
+mod chunk_codec;
 mod chunk_command;
+mod chunk_events;
 mod chunk_generator;
+mod chunk_loader;
+mod chunk_store;
 mod classes;
 mod clip_spheres;
 mod collider_generator;
 mod console_command;
+mod coordinate;
 mod inspector_egui;
+mod lod;
 mod map_database;
 mod map_generator;
 mod mesh;
 mod mesh_generator;
 mod mesh_material;
+mod occlusion;
 mod palyer;
 mod player_controller;
 mod player_ui;
@@ -56,12 +73,18 @@ mod staff;
 
 mod ray_cast;
 mod sky;
+mod view_distance;
 mod voxel;
 mod voxel_config;
+mod voxel_world;
+mod world_generate;
+mod world_save;
+mod world_verify;
 
 pub type SmallKeyHashMap<K, V> = ahash::AHashMap<K, V>;
 
 // const zone
+// 默认的视野距离 运行时使用 ViewDistance
 pub const VIEW_RADIUS: f32 = 128.00;
 pub const CHUNK_SIZE: i32 = 16;
 pub const CHUNK_SIZE_U32: u32 = CHUNK_SIZE as u32;
@@ -72,7 +95,60 @@ pub const MAX_TEXTURE_COUNT: usize = 9;
 #[derive(Debug, StructOpt)]
 enum RunMode {
     Tool,
-    Game,
+    Game {
+        /// 世界存档的目录
+        #[structopt(long, default_value = "world_test")]
+        world: String,
+        /// 新建世界时使用的种子
+        #[structopt(long)]
+        seed: Option<i32>,
+        /// 新建世界时使用的存储 sled/memory/region
+        #[structopt(long, default_value = "sled")]
+        backend: StoreBackend,
+        /// 新建世界时只保存玩家修改的体素
+        #[structopt(long)]
+        delta: bool,
+        /// 自动保存修改的间隔 秒
+        #[structopt(long, default_value = "5")]
+        autosave: f32,
+        /// 内存中最多保留的chunk个数
+        #[structopt(long, default_value = "16384")]
+        max_chunks: usize,
+    },
+    // 离线预生成地图
+    Generate {
+        /// 世界存档的目录
+        #[structopt(long, default_value = "world_test")]
+        world: String,
+        /// 新建世界时使用的种子
+        #[structopt(long)]
+        seed: Option<i32>,
+        /// 新建世界时使用的存储 sled/region
+        #[structopt(long, default_value = "sled")]
+        backend: StoreBackend,
+        /// 以出生点为中心生成的chunk半径
+        #[structopt(long, default_value = "16")]
+        radius: i32,
+        /// 指定生成范围 x0 z0 x1 z1 (chunk坐标) 设置后忽略 radius
+        #[structopt(long, number_of_values = 4, allow_hyphen_values = true)]
+        bbox: Option<Vec<i32>>,
+        #[structopt(long, default_value = "-7", allow_hyphen_values = true)]
+        min_y: i32,
+        #[structopt(long, default_value = "8")]
+        max_y: i32,
+        /// 线程数 默认使用全部核心
+        #[structopt(long)]
+        threads: Option<usize>,
+    },
+    // 检查世界数据 可以修复损坏的 chunk
+    Verify {
+        /// 世界存档的目录
+        #[structopt(long, default_value = "world_test")]
+        world: String,
+        /// 隔离损坏的 chunk 下次加载时重新生成
+        #[structopt(long)]
+        repair: bool,
+    },
 }
 
 #[bevy_main]
@@ -91,7 +167,29 @@ fn main() {
                 // .add_plugins(PlayerUiPlugin)
                 .run();
         }
-        RunMode::Game => {
+        RunMode::Game {
+            world,
+            seed,
+            backend,
+            delta,
+            autosave,
+            max_chunks,
+        } => {
+            // 打开世界 读取种子和出生点
+            let storage = if delta {
+                ChunkStorageMode::Delta
+            } else {
+                ChunkStorageMode::Full
+            };
+            let db = match MapDataBase::new(&world, seed, backend, storage) {
+                Ok(db) => db,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            let spawn = Vec3::from_array(db.meta.spawn);
+            let settings = GameSettings::read_file(SETTINGS_FILE);
             app_builder
                 .add_plugins(DefaultPlugins)
                 .add_plugins(ConsoleCommandPlugins)
@@ -109,35 +207,114 @@ fn main() {
                 .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
                 .add_plugins(PlayerControllerPlugin)
                 .add_plugins(TerrainPhysicsPlugin)
+                .add_plugins(ChunkEventsPlugin)
                 .add_plugins(ChunkCommandsPlugin)
+                .add_plugins(LodPlugin)
+                .add_plugins(WorldSavePlugin)
+                .insert_resource(AutoSaveConfig { interval: autosave })
+                .insert_resource(ChunkMapLimit {
+                    max_chunks,
+                    ..Default::default()
+                })
+                .insert_resource(ViewDistance::from_settings(&settings))
                 // .add_plugins(RapierDebugRenderPlugin::default())
                 .insert_resource(Msaa::Sample4)
                 // 这里是设置了UI
                 .add_systems(Startup, setup)
-                .add_systems(PreUpdate, (chunk_generate_system, gen_mesh_system))
+                .add_systems(
+                    PreUpdate,
+                    (
+                        apply_view_distance_system,
+                        update_chunk_anchors_system,
+                        chunk_load_merge_system,
+                        chunk_generate_system,
+                        gen_mesh_system,
+                    )
+                        .chain(),
+                )
                 .add_systems(PreUpdate, update_clip_shpere_system::<PlayerMe>)
-                .add_systems(Update, update_mesh_system.in_set(MeshSystem::UPDATE_MESH))
+                .add_systems(Update, update_mesh_system.in_set(MeshSystem::UpdateMesh))
+                .add_systems(
+                    Update,
+                    occlusion_culling_system.after(MeshSystem::UpdateMesh),
+                )
                 // 测试时使用的光源跟随
                 .add_systems(Update, light_follow_camera_system::<HeadTag>)
-                .add_systems(Last, deleter_mesh_system)
-                .run();
+                .add_systems(Last, (deleter_mesh_system, chunk_unload_system));
+            app_builder
+                .world
+                .resource_mut::<CharacterSettings>()
+                .body_position = spawn;
+            app_builder.insert_resource(db).run();
+        }
+        RunMode::Generate {
+            world,
+            seed,
+            backend,
+            radius,
+            bbox,
+            min_y,
+            max_y,
+            threads,
+        } => {
+            let db = match MapDataBase::new(&world, seed, backend, ChunkStorageMode::Full) {
+                Ok(db) => db,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            if db.meta.storage == ChunkStorageMode::Delta {
+                println!("世界[{}]只保存修改的体素 不需要预生成", world);
+                return;
+            }
+            let keys = match bbox {
+                Some(b) => keys_in_box([b[0], b[1]], [b[2], b[3]], min_y, max_y),
+                None => keys_in_radius(
+                    WorldPos(Vec3::from_array(db.meta.spawn)).chunk_key().0,
+                    radius,
+                    min_y,
+                    max_y,
+                ),
+            };
+            let threads = threads.unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(4)
+            });
+            if let Err(e) = generate_world(&db, keys, threads) {
+                eprintln!("{}", e);
+            }
+        }
+        RunMode::Verify { world, repair } => {
+            if !std::path::Path::new(&world).exists() {
+                eprintln!("世界[{}]不存在", world);
+                return;
+            }
+            // 检查时不改写元数据 元数据损坏时也能修复 chunk
+            let db = match MapDataBase::open_for_verify(&world) {
+                Ok(db) => db,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            verify_world(&db, repair);
         }
     }
 }
 
-#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
-struct ChunkFlush;
-
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<BindlessMaterial>>,
+    materials: ResMut<Assets<BindlessMaterial>>,
+    view_distance: Res<ViewDistance>,
 ) {
     // init resource of clip Spheres
     let eye = Vec3::ZERO;
     let init_shpere = Sphere3 {
         center: eye,
-        radius: VIEW_RADIUS,
+        radius: view_distance.radius,
     };
 
     let clip_spheres = ClipSpheres {
@@ -158,10 +335,12 @@ fn setup(
     commands.insert_resource(config.clone());
 
     // init resource of chunkKey offset
-    commands.insert_resource(generate_offset_resoure(VIEW_RADIUS));
+    commands.insert_resource(generate_offset_resoure(view_distance.radius, view_distance.height));
 
     // init chunkMap
+    commands.insert_resource(ChunkAnchors::default());
     commands.insert_resource(ChunkMap::new());
+    commands.insert_resource(ChunkLoadTasks::default());
 
     // init MeshManager
     commands.insert_resource(MeshManager::default());
@@ -169,12 +348,6 @@ fn setup(
     // init MeshTasks
     commands.insert_resource(MeshTasks { tasks: Vec::new() });
 
-    // init MapData
-    let mut db = MapDataBase::new("world_test");
-    // db.test_gen();
-
-    commands.insert_resource(db);
-
     // 加载材质图案
     commands.insert_resource(MaterialStorge::init_with_files(
         asset_server,
@@ -184,17 +357,15 @@ fn setup(
 
     // commands.insert_resource(DirectionalLightShadowMap { size: 4096 });
     // FIXME: 设置光源 有天空盒子不需要设置光源测试了
-    commands.spawn(
-        (PointLightBundle {
-            point_light: PointLight {
-                intensity: 10000.0,
-                shadows_enabled: true,
-                ..Default::default()
-            },
-            transform: Transform::from_translation(Vec3::new(0.0, 10.0, 0.0)),
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 10000.0,
+            shadows_enabled: true,
             ..Default::default()
-        }),
-    );
+        },
+        transform: Transform::from_translation(Vec3::new(0.0, 10.0, 0.0)),
+        ..Default::default()
+    });
 }
 
 fn light_follow_camera_system<T>(
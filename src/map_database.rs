@@ -1,41 +1,442 @@
 // 使用数据数据
 
-use std::hash::Hash;
+use std::{
+    fmt, io,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use bevy::prelude::{IVec3, Resource};
-use ndshape::{ConstShape, ConstShape3u32};
-use sled::Db;
+#[cfg(test)]
+use bevy::prelude::IVec3;
+use bevy::prelude::{warn, Resource};
+use serde::{Deserialize, Serialize};
 
-use crate::{chunk::ChunkKey, map_generator::gen_chunk_data_by_seed, voxel::Voxel, CHUNK_SIZE_U32};
+use crate::{
+    chunk::ChunkKey,
+    chunk_codec::{decode_chunk, diff_chunk, encode_chunk, encode_delta, DecodeError},
+    chunk_store::{open_store, ChunkStore, StoreBackend},
+    map_generator::gen_chunk_data_by_seed,
+    voxel::Voxel,
+};
 
-#[derive(Resource)]
+// 世界数据格式的版本 修改存储格式时增加
+// 3: 老版本 hash key 的 chunk 已经全部迁移到新的 key
+pub const WORLD_FORMAT_VERSION: u32 = 3;
+// 还有无法迁移的老 key 时停留在这个版本 下次打开时继续迁移
+const LEGACY_KEYS_FORMAT_VERSION: u32 = 2;
+pub const DEFAULT_SEED: i32 = 1512354854;
+pub const DEFAULT_GENERATOR: &str = "simdnoise";
+
+// chunk 的保存方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum ChunkStorageMode {
+    // 保存完整的 chunk
+    #[default]
+    Full,
+    // 只保存和生成地形不同的体素 读取时重新生成地形再覆盖
+    Delta,
+}
+
+// 世界的元数据 使用ron和chunk保存在同一个存储中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldMeta {
+    pub seed: i32,
+    pub generator: String,
+    pub format_version: u32,
+    // 出生点
+    pub spawn: [f32; 3],
+    // 创建和最后打开的时间 秒
+    pub created_at: u64,
+    pub last_opened_at: u64,
+    #[serde(default)]
+    pub storage: ChunkStorageMode,
+}
+
+impl WorldMeta {
+    pub fn new(seed: i32, storage: ChunkStorageMode) -> Self {
+        let now = now_secs();
+        Self {
+            seed,
+            generator: String::from(DEFAULT_GENERATOR),
+            format_version: WORLD_FORMAT_VERSION,
+            spawn: [0., 300., 0.],
+            created_at: now,
+            last_opened_at: now,
+            storage,
+        }
+    }
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub enum WorldError {
+    // 打开存储失败
+    Open(String, io::Error),
+    // 读写存储失败
+    Io(io::Error),
+    // 元数据无法解析
+    Meta(String),
+    // chunk 数据损坏
+    Decode(ChunkKey, DecodeError),
+    // 世界由更新的版本创建
+    VersionMismatch { found: u32, supported: u32 },
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorldError::Open(path, e) => write!(f, "cannot open world [{}]: {}", path, e),
+            WorldError::Io(e) => write!(f, "world io error: {}", e),
+            WorldError::Meta(e) => write!(f, "world meta is broken: {}", e),
+            WorldError::Decode(key, e) => write!(f, "chunk {:?} is broken: {}", key.0, e),
+            WorldError::VersionMismatch { found, supported } => write!(
+                f,
+                "world format version {} is newer than supported version {}",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
+
+impl From<io::Error> for WorldError {
+    fn from(e: io::Error) -> Self {
+        WorldError::Io(e)
+    }
+}
+
+#[derive(Resource, Clone)]
 pub struct MapDataBase {
-    pub db: Db,
+    pub store: Arc<dyn ChunkStore>,
+    pub meta: WorldMeta,
 }
 
 impl MapDataBase {
-    pub fn new(path: &str) -> Self {
-        let db = sled::open(path).unwrap();
-        Self { db: db }
+    // 打开世界 没有元数据时使用 seed 创建新的世界
+    pub fn new(
+        path: &str,
+        seed: Option<i32>,
+        backend: StoreBackend,
+        storage: ChunkStorageMode,
+    ) -> Result<Self, WorldError> {
+        let store = open_store(path, backend).map_err(|e| WorldError::Open(path.into(), e))?;
+        Self::with_store(store, path, seed, storage)
+    }
+
+    pub fn with_store(
+        store: Arc<dyn ChunkStore>,
+        name: &str,
+        seed: Option<i32>,
+        storage: ChunkStorageMode,
+    ) -> Result<Self, WorldError> {
+        let meta = match Self::read_meta(store.as_ref())? {
+            Some(mut meta) => {
+                if let Some(seed) = seed {
+                    if seed != meta.seed {
+                        println!(
+                            "世界[{}]已经使用种子[{}]创建 忽略参数种子[{}]",
+                            name, meta.seed, seed
+                        );
+                    }
+                }
+                if meta.format_version > WORLD_FORMAT_VERSION {
+                    return Err(WorldError::VersionMismatch {
+                        found: meta.format_version,
+                        supported: WORLD_FORMAT_VERSION,
+                    });
+                }
+                // 旧的 chunk 仍然可以读取 之后写入的是新格式 所以元数据也要升级
+                if meta.format_version < WORLD_FORMAT_VERSION {
+                    let version = Self::migrate_legacy_keys(store.as_ref(), name)?;
+                    if version > meta.format_version {
+                        println!(
+                            "世界[{}]的格式从版本[{}]升级到[{}]",
+                            name, meta.format_version, version
+                        );
+                        meta.format_version = version;
+                    }
+                }
+                meta.last_opened_at = now_secs();
+                meta
+            }
+            None => {
+                let format_version = Self::migrate_legacy_keys(store.as_ref(), name)?;
+                // 没有元数据但是已经有 chunk 的是老版本的世界 那时的地形都用默认种子生成
+                let seed = if store.iter()?.next().is_some() {
+                    if let Some(seed) = seed.filter(|seed| *seed != DEFAULT_SEED) {
+                        println!(
+                            "世界[{}]由老版本使用默认种子[{}]创建 忽略参数种子[{}]",
+                            name, DEFAULT_SEED, seed
+                        );
+                    }
+                    DEFAULT_SEED
+                } else {
+                    seed.unwrap_or(DEFAULT_SEED)
+                };
+                let mut meta = WorldMeta::new(seed, storage);
+                meta.format_version = format_version;
+                meta
+            }
+        };
+        Self::write_meta(store.as_ref(), &meta)?;
+        println!("打开世界[{}] {:?}", name, meta);
+        Ok(Self { store, meta })
+    }
+
+    // 检查世界时使用 不会写入元数据 也不会迁移和升级
+    // 元数据损坏或者没有时仍然可以遍历和修复 chunk
+    pub fn open_for_verify(path: &str) -> Result<Self, WorldError> {
+        let store =
+            open_store(path, StoreBackend::Sled).map_err(|e| WorldError::Open(path.into(), e))?;
+        Self::with_store_for_verify(store, path)
+    }
+
+    pub fn with_store_for_verify(
+        store: Arc<dyn ChunkStore>,
+        name: &str,
+    ) -> Result<Self, WorldError> {
+        let meta = match Self::read_meta(store.as_ref()) {
+            Ok(Some(meta)) => {
+                if meta.format_version > WORLD_FORMAT_VERSION {
+                    return Err(WorldError::VersionMismatch {
+                        found: meta.format_version,
+                        supported: WORLD_FORMAT_VERSION,
+                    });
+                }
+                println!("检查世界[{}] {:?}", name, meta);
+                meta
+            }
+            Ok(None) => {
+                println!("世界[{}]没有元数据 只检查chunk", name);
+                WorldMeta::new(DEFAULT_SEED, ChunkStorageMode::Full)
+            }
+            Err(e @ WorldError::Meta(_)) => {
+                println!("{} 只检查chunk", e);
+                WorldMeta::new(DEFAULT_SEED, ChunkStorageMode::Full)
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Self { store, meta })
+    }
+
+    // 打开旧的世界时先迁移老版本 key 的数据 遍历和检查才能看到全部的 chunk
+    fn migrate_legacy_keys(store: &dyn ChunkStore, name: &str) -> Result<u32, WorldError> {
+        match store.migrate_legacy_keys()? {
+            0 => Ok(WORLD_FORMAT_VERSION),
+            remaining => {
+                warn!(
+                    "世界[{}]有 {} 个chunk的老版本 key 无法迁移",
+                    name, remaining
+                );
+                Ok(LEGACY_KEYS_FORMAT_VERSION)
+            }
+        }
+    }
+
+    // 元数据损坏时不能当作新世界 否则会用新的种子覆盖
+    fn read_meta(store: &dyn ChunkStore) -> Result<Option<WorldMeta>, WorldError> {
+        match store.read_meta()? {
+            Some(data) => ron::de::from_bytes(&data)
+                .map(Some)
+                .map_err(|e| WorldError::Meta(e.to_string())),
+            None => Ok(None),
+        }
     }
 
+    fn write_meta(store: &dyn ChunkStore, meta: &WorldMeta) -> Result<(), WorldError> {
+        let data = ron::to_string(meta).map_err(|e| WorldError::Meta(e.to_string()))?;
+        store.write_meta(data.as_bytes())?;
+        Ok(())
+    }
+
+    // 读取保存的 chunk 没有保存时返回 None
+    pub fn load_chunk(&self, chunk_key: ChunkKey) -> Result<Option<Vec<Voxel>>, WorldError> {
+        match self.store.get(chunk_key)? {
+            Some(data) => decode_chunk(&data, || gen_chunk_data_by_seed(self.meta.seed, chunk_key))
+                .map(Some)
+                .map_err(|e| WorldError::Decode(chunk_key, e)),
+            None => Ok(None),
+        }
+    }
+
+    // 读取失败时不会让游戏崩溃 损坏的数据隔离后使用种子重新生成
     pub fn find_by_chunk_key(&self, chunk_key: ChunkKey) -> Vec<Voxel> {
-        let mut voxels = Vec::new();
-        type SampleShape = ConstShape3u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
-        for i in 0..SampleShape::SIZE {
-            voxels.push(Voxel::EMPTY);
-        }
-        let key = chunk_key.as_u8_array();
-        return match self.db.get(key) {
-            Ok(rs) => match rs {
-                Some(data) => bincode::deserialize(&data).unwrap(),
-                // 这里在没有获取到的情况下使用算法的值
-                None => gen_chunk_data_by_seed(1512354854, chunk_key),
-            },
+        match self.load_chunk(chunk_key) {
+            Ok(Some(voxels)) => voxels,
+            // 这里在没有获取到的情况下使用算法的值
+            Ok(None) => gen_chunk_data_by_seed(self.meta.seed, chunk_key),
+            Err(e @ WorldError::Decode(..)) => {
+                warn!("{}, quarantine it and regenerate", e);
+                self.quarantine_chunk(chunk_key);
+                gen_chunk_data_by_seed(self.meta.seed, chunk_key)
+            }
             Err(e) => {
-                println!("wrong, to get Map");
-                voxels
+                warn!("{}, regenerate chunk {:?}", e, chunk_key.0);
+                gen_chunk_data_by_seed(self.meta.seed, chunk_key)
+            }
+        }
+    }
+
+    // 把损坏的数据移出去 保留原始数据方便排查
+    pub fn quarantine_chunk(&self, chunk_key: ChunkKey) {
+        let result = match self.store.get(chunk_key) {
+            Ok(Some(data)) => self.store.quarantine(chunk_key, &data),
+            Ok(None) => Ok(()),
+            Err(_) => self.store.delete(chunk_key),
+        };
+        if let Err(e) = result {
+            warn!("wrong, to quarantine chunk {:?}: {}", chunk_key.0, e);
+        }
+    }
+
+    pub fn contains_chunk_key(&self, chunk_key: ChunkKey) -> bool {
+        match self.store.get(chunk_key) {
+            Ok(rs) => rs.is_some(),
+            Err(_) => false,
+        }
+    }
+
+    // 把修改后的 chunk 数据写回数据库 下次读取时直接使用
+    // 差量模式下和生成的地形一样时直接删除 修改多时完整保存更小
+    pub fn update_by_chunk_key(
+        &self,
+        chunk_key: ChunkKey,
+        voxels: &[Voxel],
+    ) -> Result<(), WorldError> {
+        let data = match self.meta.storage {
+            ChunkStorageMode::Full => encode_chunk(voxels),
+            ChunkStorageMode::Delta => {
+                let base = gen_chunk_data_by_seed(self.meta.seed, chunk_key);
+                let overrides = diff_chunk(&base, voxels);
+                if overrides.is_empty() {
+                    self.store.delete(chunk_key)?;
+                    return Ok(());
+                }
+                let delta = encode_delta(&overrides);
+                let full = encode_chunk(voxels);
+                if delta.len() <= full.len() {
+                    delta
+                } else {
+                    full
+                }
             }
         };
+        self.store.put(chunk_key, &data)?;
+        Ok(())
+    }
+
+    // 写入一批 chunk 并刷新 返回没有保存成功的 由调用的地方重试
+    pub fn save_chunks(&self, chunks: Vec<(ChunkKey, Vec<Voxel>)>) -> Vec<(ChunkKey, Vec<Voxel>)> {
+        let (saved, mut failed): (Vec<_>, Vec<_>) =
+            chunks.into_iter().partition(|(chunk_key, voxels)| {
+                match self.update_by_chunk_key(*chunk_key, voxels) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("wrong, to save Map {:?}: {}", chunk_key.0, e);
+                        false
+                    }
+                }
+            });
+        // 刷新失败时这一批都不能确定已经写入
+        if let Err(e) = self.store.flush() {
+            warn!("wrong, to flush Map: {}", e);
+            failed.extend(saved);
+        }
+        failed
     }
+
+    // 删除保存的修改 恢复成种子生成的地形
+    pub fn reset_chunk(&self, chunk_key: ChunkKey) -> Vec<Voxel> {
+        self.delete_by_chunk_key(chunk_key);
+        gen_chunk_data_by_seed(self.meta.seed, chunk_key)
+    }
+
+    pub fn delete_by_chunk_key(&self, chunk_key: ChunkKey) {
+        if let Err(e) = self.store.delete(chunk_key) {
+            warn!("wrong, to delete Map {:?}: {}", chunk_key, e);
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Err(e) = self.store.flush() {
+            warn!("wrong, to flush Map: {}", e);
+        }
+    }
+}
+
+#[test]
+fn test_open_v1_world() {
+    use crate::chunk_store::MemoryChunkStore;
+
+    let store: Arc<dyn ChunkStore> = Arc::new(MemoryChunkStore::default());
+    let mut meta = WorldMeta::new(7, ChunkStorageMode::Full);
+    meta.format_version = 1;
+    MapDataBase::write_meta(store.as_ref(), &meta).unwrap();
+    // 版本 1 的 chunk 直接使用 bincode 保存
+    let key = ChunkKey(IVec3::new(1, 0, -1));
+    let voxels = vec![Voxel::FILLED; 16 * 16 * 16];
+    store
+        .put(key, &bincode::serialize(&voxels).unwrap())
+        .unwrap();
+
+    let db = MapDataBase::with_store(store.clone(), "v1", None, ChunkStorageMode::Full).unwrap();
+    assert_eq!(db.meta.seed, 7);
+    assert_eq!(db.meta.format_version, WORLD_FORMAT_VERSION);
+    assert_eq!(
+        MapDataBase::read_meta(store.as_ref())
+            .unwrap()
+            .unwrap()
+            .format_version,
+        WORLD_FORMAT_VERSION
+    );
+    assert_eq!(db.load_chunk(key).unwrap().unwrap().len(), voxels.len());
+}
+
+#[test]
+fn test_open_world_without_meta() {
+    use crate::chunk_store::MemoryChunkStore;
+
+    // 已经有 chunk 的老世界 使用默认种子
+    let store: Arc<dyn ChunkStore> = Arc::new(MemoryChunkStore::default());
+    store.put(ChunkKey(IVec3::ZERO), &[1]).unwrap();
+    let db = MapDataBase::with_store(store, "old", Some(7), ChunkStorageMode::Full).unwrap();
+    assert_eq!(db.meta.seed, DEFAULT_SEED);
+
+    // 新世界使用参数种子
+    let store: Arc<dyn ChunkStore> = Arc::new(MemoryChunkStore::default());
+    let db = MapDataBase::with_store(store, "new", Some(7), ChunkStorageMode::Full).unwrap();
+    assert_eq!(db.meta.seed, 7);
+}
+
+#[test]
+fn test_open_for_verify() {
+    use crate::chunk_store::MemoryChunkStore;
+
+    // 元数据损坏时不能正常打开 检查时仍然可以遍历 chunk 并且不会改写元数据
+    let store: Arc<dyn ChunkStore> = Arc::new(MemoryChunkStore::default());
+    store.write_meta(b"broken").unwrap();
+    store.put(ChunkKey(IVec3::ZERO), &[1]).unwrap();
+    assert!(
+        MapDataBase::with_store(store.clone(), "broken", None, ChunkStorageMode::Full).is_err()
+    );
+    let db = MapDataBase::with_store_for_verify(store.clone(), "broken").unwrap();
+    assert_eq!(db.store.iter().unwrap().count(), 1);
+    assert_eq!(store.read_meta().unwrap(), Some(b"broken".to_vec()));
+
+    // 旧版本的元数据保持原样
+    let store: Arc<dyn ChunkStore> = Arc::new(MemoryChunkStore::default());
+    let mut meta = WorldMeta::new(7, ChunkStorageMode::Full);
+    meta.format_version = 1;
+    MapDataBase::write_meta(store.as_ref(), &meta).unwrap();
+    let before = store.read_meta().unwrap();
+    let db = MapDataBase::with_store_for_verify(store.clone(), "v1").unwrap();
+    assert_eq!(db.meta.format_version, 1);
+    assert_eq!(store.read_meta().unwrap(), before);
 }
@@ -1,20 +1,24 @@
-use std::f32::consts::{E, PI};
-
+use bevy::prelude::Vec3;
 use ndshape::{ConstShape, ConstShape2u32, ConstShape3u32};
 use simdnoise::NoiseBuilder;
 
 use crate::{
     chunk::ChunkKey,
+    mesh::PaddedChunkShape,
     voxel::{BasicStone, Grass, Sand, Soli, Sown, Stone, Voxel, VoxelMaterial, Water},
-    CHUNK_SIZE, CHUNK_SIZE_U32,
+    CHUNK_SIZE, CHUNK_SIZE_ADD_2_U32, CHUNK_SIZE_U32,
 };
 
+// 海平面 以下的空气会变成水
+pub const SEA_LEVEL: f32 = -60. + 76.;
+// 地表高度的范围 噪声超出时截断 远处的 LOD 只需要覆盖这个高度
+pub const TERRAIN_MIN_Y: f32 = -64.;
+pub const TERRAIN_MAX_Y: f32 = 192.;
+
 pub fn gen_chunk_data_by_seed(seed: i32, chunk_key: ChunkKey) -> Vec<Voxel> {
     // 怎么计算出
     // 这里浅浅的 试一下这个算法
-    let base_x = (chunk_key.0.x * CHUNK_SIZE) as f32;
     let base_y: f32 = (chunk_key.0.y * CHUNK_SIZE) as f32;
-    let base_z = (chunk_key.0.z * CHUNK_SIZE) as f32;
     type SampleShape = ConstShape3u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
     type PanleShap = ConstShape2u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
     let mut voxels = Vec::new();
@@ -37,69 +41,24 @@ pub fn gen_chunk_data_by_seed(seed: i32, chunk_key: ChunkKey) -> Vec<Voxel> {
 
     for i in 0..SampleShape::SIZE {
         let [x, y, z] = SampleShape::delinearize(i);
-        let p_x = base_x + x as f32;
         let p_y = base_y + y as f32;
-        let p_z = base_z + z as f32;
-
-        // let h = ((p_x * PI / 2.0).sin() + (p_z * PI / 2.0).cos());
-        let h = -60.;
-        //  + 10.0 * (p_x / 10.0).sin() + 10.0 * (p_z / 10.0).cos();
-        //  + E.powf(p_x / 3000.0)- E.powf(p_z / 3000.0);
-        // println!("({},{})", h, p_y);
         let index = PanleShap::linearize([x, z]);
-        let top = h + fn_height(noise[index as usize]) + noise2[index as usize] * 5.0;
-        // noise2[index as usize] * 5.0;
-        if p_y <= top {
-            if p_y >= -60. + 110. {
-                voxels.push(Sown::into_voxel());
-                continue;
-            }
-            if (p_y <= -110.) {
-                voxels.push(BasicStone::into_voxel());
-                continue;
-            }
-            // // 测试代码只看 截面
-            // if p_z < 0. {
-            //     voxels.push(Voxel::EMPTY);
-            //     continue;
-            // }
-            // if p_y >= 40. {
-            //     voxels.push(Sown::into_voxel());
-            //     continue;
-            // }
-            if p_y >= -60. + 100. {
-                voxels.push(Stone::into_voxel());
-                continue;
-            }
-            if p_y >= top - 1.0 {
-                if (p_y < -60. + 76.) {
-                    voxels.push(Soli::into_voxel());
-                } else {
-                    voxels.push(Grass::into_voxel());
-                }
-            } else if p_y > top - 5.0 {
-                voxels.push(Soli::into_voxel());
-            } else {
-                voxels.push(Stone::into_voxel());
-            }
-            // voxels.push(Stone::into_voxel());
-        } else {
-            voxels.push(Voxel::EMPTY);
-        }
+        let top = column_top(noise[index as usize], noise2[index as usize]);
+        voxels.push(terrain_voxel(p_y, top));
     }
     // 海平面 todo 更加优秀的还平面
     let mut water_flag = false;
     for i in 0..SampleShape::SIZE {
-        let [x, y, z] = SampleShape::delinearize(i);
+        let [_x, y, _z] = SampleShape::delinearize(i);
         let p_y: f32 = base_y + y as f32;
-        if p_y <= -60. + 76. && voxels[i as usize].id == Voxel::EMPTY.id {
+        if p_y <= SEA_LEVEL && voxels[i as usize].id == Voxel::EMPTY.id {
             water_flag = true;
             voxels[i as usize] = Water::into_voxel();
         }
     }
 
     //生成 沙子
-    if (water_flag) {
+    if water_flag {
         for i in 0..SampleShape::SIZE {
             let [x, y, z] = SampleShape::delinearize(i);
             if (check_water(voxels.clone(), [x + 1, y, z])
@@ -134,31 +93,95 @@ pub fn gen_chunk_data_by_seed(seed: i32, chunk_key: ChunkKey) -> Vec<Voxel> {
     voxels
 }
 
+// 地表的高度 两个噪声来自同一个位置
+pub fn column_top(noise: f32, ridge: f32) -> f32 {
+    let h = -60.;
+    //  + 10.0 * (p_x / 10.0).sin() + 10.0 * (p_z / 10.0).cos();
+    (h + fn_height(noise) + ridge * 5.0).clamp(TERRAIN_MIN_Y, TERRAIN_MAX_Y)
+}
+
+// 高度为 p_y 的方块 只看地形 水 沙子和洞穴之后再处理
+pub fn terrain_voxel(p_y: f32, top: f32) -> Voxel {
+    if p_y > top {
+        return Voxel::EMPTY;
+    }
+    if p_y >= -60. + 110. {
+        return Sown::into_voxel();
+    }
+    if p_y <= -110. {
+        return BasicStone::into_voxel();
+    }
+    if p_y >= -60. + 100. {
+        return Stone::into_voxel();
+    }
+    if p_y >= top - 1.0 {
+        if p_y < -60. + 76. {
+            Soli::into_voxel()
+        } else {
+            Grass::into_voxel()
+        }
+    } else if p_y > top - 5.0 {
+        Soli::into_voxel()
+    } else {
+        Stone::into_voxel()
+    }
+}
+
+// 远处 LOD 用的数据 每个格子代表 step 个方块 四周各多一格 和 PaddedChunkShape 一样大
+// origin 是第一个格子的采样点 使用生成器的坐标 只有地形和水 不生成沙子和洞穴
+// 邻居的格子也用同样的方法采样 相邻的 LOD 之间没有缝隙
+pub fn gen_lod_data_by_seed(seed: i32, origin: Vec3, step: f32) -> Vec<Voxel> {
+    type PanleShap = ConstShape2u32<CHUNK_SIZE_ADD_2_U32, CHUNK_SIZE_ADD_2_U32>;
+    let width = CHUNK_SIZE_ADD_2_U32 as usize;
+    let noise = height_noise(origin.x, origin.z, width, step, seed);
+    let noise2 = ridge_noise(origin.x, origin.z, width, step, seed);
+    let mut voxels = Vec::with_capacity(PaddedChunkShape::SIZE as usize);
+    for i in 0..PaddedChunkShape::SIZE {
+        let [x, y, z] = PaddedChunkShape::delinearize(i);
+        let p_y = origin.y + y as f32 * step;
+        let index = PanleShap::linearize([x, z]) as usize;
+        let voxel = terrain_voxel(p_y, column_top(noise[index], noise2[index]));
+        if voxel.id == Voxel::EMPTY.id && p_y <= SEA_LEVEL {
+            voxels.push(Water::into_voxel());
+        } else {
+            voxels.push(voxel);
+        }
+    }
+    voxels
+}
+
 pub fn check_water(voxels: Vec<Voxel>, point: [u32; 3]) -> bool {
     type SampleShape = ConstShape3u32<CHUNK_SIZE_U32, CHUNK_SIZE_U32, CHUNK_SIZE_U32>;
     let index = SampleShape::linearize(point);
-    if (point[0] >= CHUNK_SIZE_U32 || point[1] >= CHUNK_SIZE_U32 || point[2] >= CHUNK_SIZE_U32) {
+    if point[0] >= CHUNK_SIZE_U32 || point[1] >= CHUNK_SIZE_U32 || point[2] >= CHUNK_SIZE_U32 {
         return false;
     }
 
-    return voxels[index as usize].id == Water::ID;
+    voxels[index as usize].id == Water::ID
 }
 
 // 生成2d的柏林噪声
 pub fn noise2d(chunk_key: ChunkKey, seed: i32) -> Vec<f32> {
-    let (noise, _max, _min) = NoiseBuilder::fbm_2d_offset(
+    height_noise(
         (chunk_key.0.x * CHUNK_SIZE) as f32,
-        CHUNK_SIZE as usize,
         (chunk_key.0.z * CHUNK_SIZE) as f32,
         CHUNK_SIZE as usize,
+        1.0,
+        seed,
     )
-    .with_seed(seed)
-    .with_freq(0.05)
-    .with_octaves(4)
-    .generate();
+}
+
+// 从 (x, z) 开始每隔 step 采样一次 缩放坐标和频率 结果和逐个方块采样时一致
+pub fn height_noise(x: f32, z: f32, width: usize, step: f32, seed: i32) -> Vec<f32> {
+    let (noise, _max, _min) = NoiseBuilder::fbm_2d_offset(x / step, width, z / step, width)
+        .with_seed(seed)
+        .with_freq(0.05 * step)
+        .with_octaves(4)
+        .generate();
     noise
 }
 
+#[allow(dead_code)]
 pub fn noise3d(chunk_key: ChunkKey, seed: i32) -> Vec<f32> {
     let (noise, _max, _min) = NoiseBuilder::fbm_3d_offset(
         (chunk_key.0.x * CHUNK_SIZE) as f32,
@@ -178,24 +201,29 @@ pub fn noise3d(chunk_key: ChunkKey, seed: i32) -> Vec<f32> {
 }
 
 pub fn noise2d_ridge(chunk_key: ChunkKey, seed: i32) -> Vec<f32> {
-    let (noise, min, max) = NoiseBuilder::ridge_2d_offset(
+    ridge_noise(
         (chunk_key.0.x * CHUNK_SIZE) as f32,
-        CHUNK_SIZE as usize,
         (chunk_key.0.z * CHUNK_SIZE) as f32,
         CHUNK_SIZE as usize,
+        1.0,
+        seed,
     )
-    .with_seed(seed)
-    .with_freq(0.03)
-    .with_octaves(5)
-    .with_gain(4.0)
-    .with_lacunarity(0.5)
-    .generate();
+}
+
+pub fn ridge_noise(x: f32, z: f32, width: usize, step: f32, seed: i32) -> Vec<f32> {
+    let (noise, _min, _max) = NoiseBuilder::ridge_2d_offset(x / step, width, z / step, width)
+        .with_seed(seed)
+        .with_freq(0.03 * step)
+        .with_octaves(5)
+        .with_gain(4.0)
+        .with_lacunarity(0.5)
+        .generate();
     noise
 }
 
 // todo: 尝试产生 洞穴的噪声
 pub fn noise3d_2(chunk_key: ChunkKey, seed: i32) -> Vec<f32> {
-    let (noise, min, max) = NoiseBuilder::fbm_3d_offset(
+    let (noise, _min, _max) = NoiseBuilder::fbm_3d_offset(
         (chunk_key.0.x * CHUNK_SIZE) as f32,
         CHUNK_SIZE as usize,
         (chunk_key.0.y * CHUNK_SIZE) as f32,
@@ -218,18 +246,18 @@ pub fn fn_height(x: f32) -> f32 {
         // print!("a{}", x);
         return 60.;
     }
-    if x >= -0.6 && x < -0.5 {
+    if (-0.6..-0.5).contains(&x) {
         // print!("b{}", x);
         return 60. + 150. * (x - 0.6);
     }
-    if x >= -0.5 && x < 0.0 {
+    if (-0.5..0.0).contains(&x) {
         // print!("c{}", x);
         return 75.;
     }
-    if x >= 0.0 && x < 0.1 {
+    if (0.0..0.1).contains(&x) {
         return 75. + 100. * x;
     }
-    if x >= 0.1 && x < 0.2 {
+    if (0.1..0.2).contains(&x) {
         return 85. + 150. * (x - 0.1);
     }
     if x >= 0.2 {
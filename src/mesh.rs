@@ -1,11 +1,10 @@
 use bevy::{
-    prelude::{Mesh, Vec3},
+    prelude::Mesh,
     render::{
         mesh::{Indices, VertexAttributeValues},
         render_resource::PrimitiveTopology,
     },
 };
-use bevy_rapier3d::prelude::Collider;
 use block_mesh::{greedy_quads, GreedyQuadsBuffer, RIGHT_HANDED_Y_UP_CONFIG};
 use ndshape::{ConstShape, ConstShape3u32};
 
@@ -16,11 +15,15 @@ use crate::{
     CHUNK_SIZE, CHUNK_SIZE_ADD_2_U32,
 };
 
+// 生成 mesh 时的数据 四周各多一格邻居的数据
+pub type PaddedChunkShape =
+    ConstShape3u32<CHUNK_SIZE_ADD_2_U32, CHUNK_SIZE_ADD_2_U32, CHUNK_SIZE_ADD_2_U32>;
+
 pub fn gen_mesh(
     voxels: Vec<Voxel>,
     material_config: MaterailConfiguration,
 ) -> Option<Mesh> {
-    type SampleShape = ConstShape3u32<CHUNK_SIZE_ADD_2_U32, 256, CHUNK_SIZE_ADD_2_U32>;
+    type SampleShape = PaddedChunkShape;
     let mut buffer = GreedyQuadsBuffer::new(SampleShape::SIZE as usize);
     let faces: [block_mesh::OrientedBlockFace; 6] = RIGHT_HANDED_Y_UP_CONFIG.faces;
     // let padding_voxels = padding_extents(voxels);
@@ -28,7 +31,7 @@ pub fn gen_mesh(
         &voxels,
         &SampleShape {},
         [0; 3],
-        [(CHUNK_SIZE + 1) as u32, 255, (CHUNK_SIZE + 1) as u32],
+        [(CHUNK_SIZE + 1) as u32; 3],
         &faces,
         &mut buffer,
     );
@@ -47,18 +50,18 @@ pub fn gen_mesh(
         .quads
         .groups
         .as_ref()
-        .into_iter()
-        .zip(faces.into_iter())
+        .iter()
+        .zip(faces)
         .enumerate()
     {
-        for quad in group.into_iter() {
+        for quad in group.iter() {
             indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
-            positions.extend_from_slice(&face.quad_mesh_positions(&quad, 1.0));
+            positions.extend_from_slice(&face.quad_mesh_positions(quad, 1.0));
             normals.extend_from_slice(&face.quad_mesh_normals());
             tex_coords.extend_from_slice(&face.tex_coords(
                 RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
                 true,
-                &quad,
+                quad,
             ));
             // 这里可以生成Data???? 但是怎么知道 是那个面的？
             let index = SampleShape::linearize(quad.minimum);
@@ -73,7 +76,7 @@ pub fn gen_mesh(
             );
             // voxels[index as usize].id
             // todo 这里后面要知道是那个面的方便渲染
-            data.extend_from_slice(&[normol_num | (txt_index) as u32; 4]);
+            data.extend_from_slice(&[normol_num | txt_index; 4]);
         }
     }
 
@@ -93,7 +96,7 @@ pub fn gen_mesh(
 
 // 生成水的mesh
 pub fn gen_mesh_water(voxels: Vec<Voxel>, material_config: MaterailConfiguration) -> Option<Mesh> {
-    type SampleShape = ConstShape3u32<CHUNK_SIZE_ADD_2_U32, 256, CHUNK_SIZE_ADD_2_U32>;
+    type SampleShape = PaddedChunkShape;
     let mut buffer = GreedyQuadsBuffer::new(SampleShape::SIZE as usize);
     let faces: [block_mesh::OrientedBlockFace; 6] = RIGHT_HANDED_Y_UP_CONFIG.faces;
     // let water_voxels = pick_water(voxels);
@@ -101,7 +104,7 @@ pub fn gen_mesh_water(voxels: Vec<Voxel>, material_config: MaterailConfiguration
         &voxels,
         &SampleShape {},
         [0; 3],
-        [(CHUNK_SIZE + 1) as u32, 255, (CHUNK_SIZE + 1) as u32],
+        [(CHUNK_SIZE + 1) as u32; 3],
         &faces,
         &mut buffer,
     );
@@ -120,18 +123,18 @@ pub fn gen_mesh_water(voxels: Vec<Voxel>, material_config: MaterailConfiguration
         .quads
         .groups
         .as_ref()
-        .into_iter()
-        .zip(faces.into_iter())
+        .iter()
+        .zip(faces)
         .enumerate()
     {
-        for quad in group.into_iter() {
+        for quad in group.iter() {
             indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
-            positions.extend_from_slice(&face.quad_mesh_positions(&quad, 1.0));
+            positions.extend_from_slice(&face.quad_mesh_positions(quad, 1.0));
             normals.extend_from_slice(&face.quad_mesh_normals());
             tex_coords.extend_from_slice(&face.tex_coords(
                 RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
                 true,
-                &quad,
+                quad,
             ));
             // 法向量值
             let normol_num = (block_face_normal_index as u32) << 8u32;
@@ -143,7 +146,7 @@ pub fn gen_mesh_water(voxels: Vec<Voxel>, material_config: MaterailConfiguration
             );
             // voxels[index as usize].id
             // todo 这里后面要知道是那个面的方便渲染
-            data.extend_from_slice(&[normol_num | (txt_index) as u32; 4]);
+            data.extend_from_slice(&[normol_num | txt_index; 4]);
         }
     }
 
@@ -2,26 +2,26 @@ use std::collections::HashSet;
 
 use bevy::{
     prelude::{
-        AlphaMode, Assets, Color, Commands, Entity, Handle, MaterialMeshBundle, Mesh, PbrBundle,
-        Res, ResMut, Resource, StandardMaterial, SystemSet, Transform,
+        AlphaMode, Assets, Color, Commands, Entity, EventWriter, GlobalTransform, Handle,
+        MaterialMeshBundle, Mesh, Query, Res, ResMut, Resource, StandardMaterial, SystemSet,
+        Transform, With,
     },
     tasks::{AsyncComputeTaskPool, Task},
 };
-use bevy_rapier3d::{
-    prelude::{Collider, RigidBody},
-    rapier::prelude::{RigidBodyType, SharedShape},
-};
-use bincode::de;
+use controller::controller::CameraTag;
 
 use crate::{
-    chunk::{find_chunk_keys_array_by_shpere_y_0, ChunkKey, NeighbourOffest},
+    chunk::{sort_by_load_priority, ChunkKey},
+    chunk_events::ChunkMeshed,
     chunk_generator::ChunkMap,
+    chunk_loader::ChunkAnchors,
     clip_spheres::ClipSpheres,
     mesh::{gen_mesh, gen_mesh_water, pick_water},
     mesh_material::MaterialStorge,
+    occlusion::SectionConnectivity,
     voxel::Voxel,
     voxel_config::MaterailConfiguration,
-    SmallKeyHashMap, CHUNK_SIZE, VIEW_RADIUS,
+    SmallKeyHashMap,
 };
 
 #[derive(Debug, Clone, Resource, Default)]
@@ -31,41 +31,106 @@ pub struct MeshManager {
     pub entities: SmallKeyHashMap<ChunkKey, Entity>,
     pub water_entities: SmallKeyHashMap<ChunkKey, Entity>,
     pub fast_key: HashSet<ChunkKey>,
+    // 后台生成中的 mesh 的编号 数据被修改后作废 旧的结果不再添加
+    pub pending: SmallKeyHashMap<ChunkKey, u64>,
+    pub next_task_id: u64,
+    // 每个 section 的面之间是否连通 用来剔除看不到的 section
+    pub connectivity: SmallKeyHashMap<ChunkKey, SectionConnectivity>,
+}
+
+impl MeshManager {
+    pub fn start_task(&mut self, chunk_key: ChunkKey) -> u64 {
+        self.next_task_id += 1;
+        self.fast_key.insert(chunk_key);
+        self.pending.insert(chunk_key, self.next_task_id);
+        self.next_task_id
+    }
+
+    // 是否是最新的任务 是的话结束等待
+    pub fn finish_task(&mut self, chunk_key: ChunkKey, task_id: u64) -> bool {
+        if self.pending.get(&chunk_key) != Some(&task_id) {
+            return false;
+        }
+        self.pending.remove(&chunk_key);
+        true
+    }
+
+    // 作废生成中的 mesh 之后由 gen_mesh_system 用新的数据重新生成
+    pub fn cancel_task(&mut self, chunk_key: ChunkKey) -> bool {
+        if self.pending.remove(&chunk_key).is_none() {
+            return false;
+        }
+        self.fast_key.remove(&chunk_key);
+        true
+    }
 }
 
+// 后台生成好的 mesh (地形, 水)
 #[derive(Resource)]
 pub struct MeshTasks {
-    pub tasks: Vec<Task<(Vec<Voxel>, ChunkKey)>>,
+    #[allow(clippy::type_complexity)]
+    pub tasks: Vec<
+        Task<(
+            ChunkKey,
+            u64,
+            Option<Mesh>,
+            Option<Mesh>,
+            SectionConnectivity,
+        )>,
+    >,
 }
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum MeshSystem {
-    UPDATE_MESH,
+    UpdateMesh,
 }
 
+// 每帧最多开始的 mesh 任务 拼接邻居数据在主线程上
+pub const MAX_MESH_TASKS_PER_FRAME: usize = 64;
+
 pub fn gen_mesh_system(
-    mut chunk_map: ResMut<ChunkMap>,
+    chunk_map: Res<ChunkMap>,
     mut mesh_manager: ResMut<MeshManager>,
     clip_spheres: Res<ClipSpheres>,
-    neighbour_offest: Res<NeighbourOffest>,
+    chunk_anchors: Res<ChunkAnchors>,
     mut mesh_task: ResMut<MeshTasks>,
+    material_config: Res<MaterailConfiguration>,
+    camera: Query<&GlobalTransform, With<CameraTag>>,
 ) {
     let pool = AsyncComputeTaskPool::get();
-    for key in
-        find_chunk_keys_array_by_shpere_y_0(clip_spheres.new_sphere, neighbour_offest.0.clone())
-            .drain(..)
-    {
-        if !mesh_manager.entities.contains_key(&key) && !mesh_manager.fast_key.contains(&key) {
-            if (!chunk_map.map_data.contains_key(&key)) {
-                // 这里没有加载好地图数据前 先不加载数据
-                return;
-            }
-            // 无论如何都插入进去 放置下次重复检查
-            mesh_manager.fast_key.insert(key);
-            let volexs: Vec<Voxel> = chunk_map.get_with_neighbor_full_y(key);
-            let task = pool.spawn(async move { (volexs.clone(), key.clone()) });
-            mesh_task.tasks.push(task);
+    let mut keys: Vec<ChunkKey> = chunk_anchors
+        .keys
+        .iter()
+        .filter(|key| {
+            !mesh_manager.entities.contains_key(key) && !mesh_manager.fast_key.contains(key)
+        })
+        .cloned()
+        .collect();
+    let forward = camera.get_single().ok().map(|tfr| tfr.forward());
+    sort_by_load_priority(&mut keys, clip_spheres.new_sphere.center, forward);
+
+    let mut started = 0;
+    for key in keys {
+        if started >= MAX_MESH_TASKS_PER_FRAME {
+            break;
+        }
+        if !chunk_map.section_ready(key) {
+            // 这里没有加载好地图数据前 先跳过 不影响后面的
+            continue;
         }
+        started += 1;
+        // 无论如何都插入进去 放置下次重复检查
+        let task_id = mesh_manager.start_task(key);
+        let volexs: Vec<Voxel> = chunk_map.get_with_neighbor(key);
+        let material_config = material_config.clone();
+        // greedy meshing 在后台完成 主线程只负责添加资源
+        let task = pool.spawn(async move {
+            let mesh = gen_mesh(volexs.clone(), material_config.clone());
+            let connectivity = SectionConnectivity::from_padded(&volexs);
+            let water_mesh = gen_mesh_water(pick_water(volexs), material_config);
+            (key, task_id, mesh, water_mesh, connectivity)
+        });
+        mesh_task.tasks.push(task);
     }
 }
 
@@ -75,117 +140,107 @@ pub fn update_mesh_system(
     mut mesh_assets: ResMut<Assets<Mesh>>,
     mut mesh_task: ResMut<MeshTasks>,
     materials: Res<MaterialStorge>,
-    material_config: Res<MaterailConfiguration>,
     mut materials_assets: ResMut<Assets<StandardMaterial>>,
+    mut meshed_events: EventWriter<ChunkMeshed>,
 ) {
-    let l = mesh_task.tasks.len().min(3);
-    for ele in mesh_task.tasks.drain(..l) {
-        match futures_lite::future::block_on(futures_lite::future::poll_once(ele)) {
-            Some((voxels, chunk_key)) => {
-                if mesh_manager.entities.contains_key(&chunk_key) {
-                    return;
-                } else {
-                    match gen_mesh(voxels.to_owned(), material_config.clone()) {
-                        Some(render_mesh) => {
-                            let mesh_handle = mesh_assets.add(render_mesh);
-                            mesh_manager
-                                .mesh_storge
-                                .insert(chunk_key, mesh_handle.clone());
-                            mesh_manager.entities.insert(
-                                chunk_key,
-                                commands
-                                    .spawn(MaterialMeshBundle {
-                                        transform: Transform::from_xyz(
-                                            (chunk_key.0.x * CHUNK_SIZE) as f32
-                                                - CHUNK_SIZE as f32 / 2.0
-                                                - 1.0,
-                                            -128.0 + CHUNK_SIZE as f32 / 2.0,
-                                            (chunk_key.0.z * CHUNK_SIZE) as f32
-                                                - CHUNK_SIZE as f32 / 2.0
-                                                - 1.0,
-                                        ),
-                                        mesh: mesh_handle.clone(),
-                                        material: materials.0.clone(),
-                                        ..Default::default()
-                                    })
-                                    .id(),
-                            );
-                        }
-                        None => {}
-                    };
-                    match gen_mesh_water(pick_water(voxels.clone()), material_config.clone()) {
-                        Some(water_mesh) => {
-                            let water_mesh_handle = mesh_assets.add(water_mesh);
-                            mesh_manager
-                                .water_mesh_storge
-                                .insert(chunk_key, water_mesh_handle.clone());
-                            mesh_manager.water_entities.insert(
-                                chunk_key,
-                                commands
-                                    .spawn(MaterialMeshBundle {
-                                        transform: Transform::from_xyz(
-                                            (chunk_key.0.x * CHUNK_SIZE) as f32
-                                                - CHUNK_SIZE as f32 / 2.0
-                                                - 1.0,
-                                            -128.0 + CHUNK_SIZE as f32 / 2.0,
-                                            (chunk_key.0.z * CHUNK_SIZE) as f32
-                                                - CHUNK_SIZE as f32 / 2.0
-                                                - 1.0,
-                                        ),
-                                        mesh: water_mesh_handle,
-                                        material: materials_assets.add(StandardMaterial {
-                                            base_color: Color::rgba(
-                                                10. / 255.,
-                                                18. / 255.,
-                                                246. / 255.,
-                                                0.6,
-                                            ),
-                                            alpha_mode: AlphaMode::Blend,
-                                            ..Default::default()
-                                        }),
-                                        ..Default::default()
-                                    })
-                                    .id(),
-                            );
-                        }
-                        None => {}
-                    }
-                }
+    let mut finished = Vec::new();
+    mesh_task.tasks.retain_mut(|task| {
+        match futures_lite::future::block_on(futures_lite::future::poll_once(task)) {
+            Some(result) => {
+                finished.push(result);
+                false
             }
-            None => {}
+            None => true,
+        }
+    });
+    for (chunk_key, task_id, mesh, water_mesh, connectivity) in finished {
+        // 生成期间已经移出视野 或者数据被修改过的不再添加
+        if !mesh_manager.finish_task(chunk_key, task_id) {
+            continue;
+        }
+        mesh_manager.connectivity.insert(chunk_key, connectivity);
+        let transform = Transform::from_translation(chunk_key.mesh_origin());
+        if let Some(render_mesh) = mesh {
+            let mesh_handle = mesh_assets.add(render_mesh);
+            mesh_manager
+                .mesh_storge
+                .insert(chunk_key, mesh_handle.clone());
+            mesh_manager.entities.insert(
+                chunk_key,
+                commands
+                    .spawn(MaterialMeshBundle {
+                        transform,
+                        mesh: mesh_handle.clone(),
+                        material: materials.0.clone(),
+                        ..Default::default()
+                    })
+                    .id(),
+            );
+        };
+        if let Some(water_mesh) = water_mesh {
+            let water_mesh_handle = mesh_assets.add(water_mesh);
+            mesh_manager
+                .water_mesh_storge
+                .insert(chunk_key, water_mesh_handle.clone());
+            mesh_manager.water_entities.insert(
+                chunk_key,
+                commands
+                    .spawn(MaterialMeshBundle {
+                        transform,
+                        mesh: water_mesh_handle,
+                        material: materials_assets.add(StandardMaterial {
+                            base_color: Color::rgba(10. / 255., 18. / 255., 246. / 255., 0.6),
+                            alpha_mode: AlphaMode::Blend,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    })
+                    .id(),
+            );
         }
+        meshed_events.send(ChunkMeshed { chunk_key });
     }
 }
 
+// 删除所有锚点视野外的 mesh
 pub fn deleter_mesh_system(
     mut commands: Commands,
     mut mesh_manager: ResMut<MeshManager>,
-    neighbour_offest: Res<NeighbourOffest>,
-    clip_spheres: Res<ClipSpheres>,
+    chunk_anchors: Res<ChunkAnchors>,
 ) {
-    let mut chunks_to_remove = HashSet::new();
-    for key in
-        find_chunk_keys_array_by_shpere_y_0(clip_spheres.old_sphere, neighbour_offest.0.clone())
-            .drain(..)
-    {
-        chunks_to_remove.insert(key);
-    }
-
-    for key in
-        find_chunk_keys_array_by_shpere_y_0(clip_spheres.new_sphere, neighbour_offest.0.clone())
-            .drain(..)
-    {
-        chunks_to_remove.remove(&key);
-    }
+    let chunks_to_remove: Vec<ChunkKey> = mesh_manager
+        .fast_key
+        .iter()
+        .filter(|key| !chunk_anchors.contains(**key, 0))
+        .cloned()
+        .collect();
 
     for chunk_key in chunks_to_remove.into_iter() {
+        // 还在后台生成的 mesh 完成后也不再添加
+        mesh_manager.fast_key.remove(&chunk_key);
+        mesh_manager.pending.remove(&chunk_key);
+        mesh_manager.connectivity.remove(&chunk_key);
         if let Some(entity) = mesh_manager.entities.remove(&chunk_key) {
-            mesh_manager.fast_key.remove(&chunk_key);
             commands.entity(entity).despawn();
         }
         if let Some(entity) = mesh_manager.water_entities.remove(&chunk_key) {
-            mesh_manager.fast_key.remove(&chunk_key);
             commands.entity(entity).despawn();
         }
     }
 }
+
+#[test]
+fn test_mesh_task() {
+    let mut mesh_manager = MeshManager::default();
+    let key = ChunkKey(bevy::prelude::IVec3::new(1, 2, 3));
+    let old_task = mesh_manager.start_task(key);
+    // 生成期间数据被修改 旧的结果作废
+    assert!(mesh_manager.cancel_task(key));
+    assert!(!mesh_manager.fast_key.contains(&key));
+    let new_task = mesh_manager.start_task(key);
+    assert!(!mesh_manager.finish_task(key, old_task));
+    assert!(mesh_manager.finish_task(key, new_task));
+    // 已经完成的不能再作废
+    assert!(!mesh_manager.cancel_task(key));
+    assert!(mesh_manager.fast_key.contains(&key));
+}
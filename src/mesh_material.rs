@@ -132,10 +132,10 @@ impl Material for BindlessMaterial {
     }
 
     fn specialize(
-        pipeline: &bevy::pbr::MaterialPipeline<Self>,
+        _pipeline: &bevy::pbr::MaterialPipeline<Self>,
         descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
         layout: &bevy::render::mesh::MeshVertexBufferLayout,
-        key: bevy::pbr::MaterialPipelineKey<Self>,
+        _key: bevy::pbr::MaterialPipelineKey<Self>,
     ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
         let vertex_layout = layout.get_layout(&[
             Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
@@ -170,11 +170,12 @@ pub const ATTRIBUTE_DATA: MeshVertexAttribute =
 pub struct MaterialStorge(pub Handle<BindlessMaterial>);
 
 impl MaterialStorge {
+    #[allow(dead_code)]
     pub fn init(
         asset_server: Res<AssetServer>,
         mut materials: ResMut<Assets<BindlessMaterial>>,
     ) -> Self {
-        let textures: Vec<_> = Voxel::VoxelIds
+        let textures: Vec<_> = Voxel::VOXEL_IDS
             .iter()
             .map(|id| {
                 // 这里是文件生成的规则 0x,xx
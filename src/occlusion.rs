@@ -0,0 +1,190 @@
+// 洞穴和遮挡剔除
+// 生成 mesh 时记录 section 的哪些面之间可以通过空气 (或水) 连通
+// 之后从相机所在的 section 开始扩散 走不到的 section 不显示
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::{DetectChanges, GlobalTransform, IVec3, Local, Query, Res, Visibility, With};
+use controller::controller::CameraTag;
+use ndshape::ConstShape;
+
+use crate::{
+    chunk::{ChunkKey, NEIGHBOUR_SIDES},
+    coordinate::{ChunkShape, WorldPos},
+    mesh::PaddedChunkShape,
+    mesh_generator::MeshManager,
+    voxel::Voxel,
+    voxel_world::is_solid,
+    SmallKeyHashMap, CHUNK_SIZE,
+};
+
+// 面的顺序和 NEIGHBOUR_SIDES 一样 相对的面是 face ^ 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SectionConnectivity(pub u64);
+
+impl SectionConnectivity {
+    pub const ALL: Self = Self((1 << 36) - 1);
+
+    pub fn connects(&self, from: usize, to: usize) -> bool {
+        self.0 & (1 << (from * 6 + to)) != 0
+    }
+
+    fn connect_faces(&mut self, faces: u8) {
+        for a in 0..6 {
+            for b in 0..6 {
+                if faces & (1 << a) != 0 && faces & (1 << b) != 0 {
+                    self.0 |= 1 << (a * 6 + b);
+                }
+            }
+        }
+    }
+
+    // 使用生成 mesh 的数据 只看中间的 section 部分
+    pub fn from_padded(voxels: &[Voxel]) -> Self {
+        let opaque = |local: IVec3| {
+            let [x, y, z] = (local + IVec3::ONE).as_uvec3().to_array();
+            is_solid(voxels[PaddedChunkShape::linearize([x, y, z]) as usize])
+        };
+        let mut result = Self::default();
+        let mut visited = vec![false; ChunkShape::SIZE as usize];
+        let mut stack = Vec::new();
+        for start in 0..ChunkShape::SIZE {
+            let start_pos = IVec3::from_array(ChunkShape::delinearize(start).map(|v| v as i32));
+            if visited[start as usize] || opaque(start_pos) {
+                continue;
+            }
+            // 一块连通的空气碰到了哪些面
+            let mut faces = 0u8;
+            visited[start as usize] = true;
+            stack.push(start_pos);
+            while let Some(pos) = stack.pop() {
+                for (face, side) in NEIGHBOUR_SIDES.iter().enumerate() {
+                    let next = pos + *side;
+                    if next.min_element() < 0 || next.max_element() >= CHUNK_SIZE {
+                        faces |= 1 << face;
+                        continue;
+                    }
+                    let index = ChunkShape::linearize(next.as_uvec3().to_array()) as usize;
+                    if !visited[index] && !opaque(next) {
+                        visited[index] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+            result.connect_faces(faces);
+        }
+        result
+    }
+}
+
+// 从 start 开始向外扩散 只能从进入的面走到连通的面 并且不能往回走
+// 还没有连通数据的 section 当作全部连通 in_view 之外的不再继续
+pub fn visible_sections(
+    start: ChunkKey,
+    connectivity: &SmallKeyHashMap<ChunkKey, SectionConnectivity>,
+    in_view: impl Fn(ChunkKey) -> bool,
+) -> HashSet<ChunkKey> {
+    let mut visible = HashSet::new();
+    visible.insert(start);
+    let mut queue = VecDeque::new();
+    queue.push_back((start, None, 0u8));
+    while let Some((key, from, directions)) = queue.pop_front() {
+        let section = connectivity
+            .get(&key)
+            .copied()
+            .unwrap_or(SectionConnectivity::ALL);
+        for (face, side) in NEIGHBOUR_SIDES.iter().enumerate() {
+            if directions & (1 << (face ^ 1)) != 0 {
+                continue;
+            }
+            if let Some(from) = from {
+                if !section.connects(from, face) {
+                    continue;
+                }
+            }
+            let next = ChunkKey(key.0 + *side);
+            if visible.contains(&next) || !in_view(next) {
+                continue;
+            }
+            visible.insert(next);
+            queue.push_back((next, Some(face ^ 1), directions | (1 << face)));
+        }
+    }
+    visible
+}
+
+// 相机换了 section 或者 mesh 有变化时重新计算
+// 刚生成的实体可能还没有添加到世界中 这时下一帧再算一次
+pub fn occlusion_culling_system(
+    mesh_manager: Res<MeshManager>,
+    camera: Query<&GlobalTransform, With<CameraTag>>,
+    mut visibilities: Query<&mut Visibility>,
+    mut last: Local<(Option<ChunkKey>, bool)>,
+) {
+    let Ok(tfr) = camera.get_single() else {
+        return;
+    };
+    let start = WorldPos(tfr.translation()).chunk_key();
+    if last.0 == Some(start) && !last.1 && !mesh_manager.is_changed() {
+        return;
+    }
+    let visible = visible_sections(start, &mesh_manager.connectivity, |key| {
+        mesh_manager.fast_key.contains(&key)
+    });
+    let mut missing = false;
+    for (key, entity) in mesh_manager
+        .entities
+        .iter()
+        .chain(mesh_manager.water_entities.iter())
+    {
+        let Ok(mut visibility) = visibilities.get_mut(*entity) else {
+            missing = true;
+            continue;
+        };
+        let wanted = if visible.contains(key) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        // 没有变化时不修改 避免触发变更检测
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+    *last = (Some(start), missing);
+}
+
+#[test]
+fn test_section_connectivity() {
+    let size = PaddedChunkShape::SIZE as usize;
+    assert_eq!(
+        SectionConnectivity::from_padded(&vec![Voxel::EMPTY; size]),
+        SectionConnectivity::ALL
+    );
+    assert_eq!(
+        SectionConnectivity::from_padded(&vec![Voxel::FILLED; size]),
+        SectionConnectivity::default()
+    );
+
+    // 只有一条沿着 x 方向的隧道
+    let mut voxels = vec![Voxel::FILLED; size];
+    for x in 1..=CHUNK_SIZE as u32 {
+        voxels[PaddedChunkShape::linearize([x, 5, 5]) as usize] = Voxel::EMPTY;
+    }
+    let tunnel = SectionConnectivity::from_padded(&voxels);
+    assert!(tunnel.connects(0, 1));
+    assert!(tunnel.connects(1, 0));
+    assert!(!tunnel.connects(1, 2));
+    assert!(!tunnel.connects(4, 5));
+
+    // 一排 section 中间的被填满 后面的看不到
+    let mut connectivity = SmallKeyHashMap::new();
+    connectivity.insert(
+        ChunkKey(IVec3::new(2, 0, 0)),
+        SectionConnectivity::default(),
+    );
+    let in_view = |key: ChunkKey| key.0.y == 0 && key.0.z == 0 && key.0.x.abs() <= 4;
+    let visible = visible_sections(ChunkKey(IVec3::ZERO), &connectivity, in_view);
+    assert!(visible.contains(&ChunkKey(IVec3::new(2, 0, 0))));
+    assert!(!visible.contains(&ChunkKey(IVec3::new(3, 0, 0))));
+    assert!(visible.contains(&ChunkKey(IVec3::new(-4, 0, 0))));
+}
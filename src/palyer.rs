@@ -4,16 +4,16 @@ use bevy::input::mouse::MouseMotion;
 use bevy::prelude::{
     warn, Added, App, AssetServer, Camera3dBundle, Commands, Component, Entity,
     EnvironmentMapLight, EulerRot, Events, Input, KeyCode, Plugin, PreUpdate, Quat, Query, Res,
-    ResMut, Resource, Startup, Transform, Update, Vec2, Vec3, With, World,
+    ResMut, Resource, Startup, Transform, Update, Vec2, Vec3, With,
 };
 use bevy::time::Time;
 use bevy::window::{CursorGrabMode, PrimaryWindow, Window};
 // use bevy_atmosphere::prelude::AtmosphereCamera;
 use bevy_egui::egui::epaint::Shadow;
-use bevy_egui::egui::{self, Color32, Pos2, Stroke};
+use bevy_egui::egui::{self, Color32};
 use bevy_egui::EguiContext;
 use bevy_rapier3d::prelude::{Collider, LockedAxes};
-use bevy_rapier3d::prelude::{QueryFilter, RigidBody};
+use bevy_rapier3d::prelude::RigidBody;
 
 #[derive(Debug, Component)]
 pub struct PlayerController;
@@ -187,6 +187,7 @@ fn cursor_grab(
     }
 }
 
+#[allow(dead_code)]
 fn initial_grab_on_flycam_spawn(
     mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
     query_added: Query<Entity, Added<PlayerController>>,
@@ -288,7 +289,7 @@ pub fn egui_center_cursor_system(
 
     egui::CentralPanel::default()
         .frame(my_frame)
-        .show(&egui_context.get_mut(), |ui| {
+        .show(egui_context.get_mut(), |ui| {
             //  = Color32::TRANSPARENT;
 
             // let size = ui.available_size();
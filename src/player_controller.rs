@@ -1,16 +1,16 @@
 use bevy::{
     prelude::{
         shape, App, Assets, BuildChildren, Camera3dBundle, ClearColor, Color, Commands, Component,
-        ComputedVisibility, Entity, FogFalloff, FogSettings, GlobalTransform, Input, KeyCode, Mat4,
-        Mesh, PbrBundle, Plugin, PreUpdate, Quat, Query, Res, ResMut, StandardMaterial, Startup,
-        Transform, Update, Vec3, Visibility,
+        ComputedVisibility, Entity, GlobalTransform, Input, KeyCode, Mat4, Mesh, PbrBundle, Plugin,
+        PreUpdate, Quat, Query, Res, ResMut, StandardMaterial, Startup, Transform, Update, Vec3,
+        Visibility,
     },
     transform::TransformBundle,
 };
 use bevy_atmosphere::prelude::AtmosphereCamera;
 use bevy_rapier3d::prelude::{
-    Ccd, CoefficientCombineRule, Collider, ColliderMassProperties, Damping, Friction, LockedAxes,
-    NoUserData, RapierPhysicsPlugin, Restitution, RigidBody, Sleeping,
+    CoefficientCombineRule, Collider, ColliderMassProperties, Damping, Friction, LockedAxes,
+    Restitution, RigidBody, Sleeping,
 };
 use controller::{
     controller::{
@@ -22,7 +22,10 @@ use controller::{
     utils::CharacterSettings,
 };
 
-use crate::palyer::{egui_center_cursor_system, PlayerStorge};
+use crate::{
+    chunk_loader::ChunkLoader,
+    palyer::{egui_center_cursor_system, PlayerStorge},
+};
 
 // 角色操作使用rapier的插件
 pub struct PlayerControllerPlugin;
@@ -71,6 +74,8 @@ pub fn spawn_character(
             Visibility::Inherited,
             ComputedVisibility::HIDDEN,
             PlayerMe,
+            // 角色周围的 chunk 跟随设置中的视野距离加载
+            ChunkLoader::follow_view(),
         ))
         .insert(RigidBody::Dynamic)
         // .insert(Ccd::disabled())
@@ -95,8 +100,8 @@ pub fn spawn_character(
         .insert(LockedAxes::ROTATION_LOCKED)
         .insert(ColliderMassProperties::Density(300.0))
         .insert(Collider::capsule(
-            (-0.5 * character_settings.scale.y * Vec3::Y).into(),
-            (0.5 * (character_settings.scale.y - 0.9) * Vec3::Y).into(),
+            -0.5 * character_settings.scale.y * Vec3::Y,
+            0.5 * (character_settings.scale.y - 0.9) * Vec3::Y,
             0.5 * character_settings.scale.x.max(character_settings.scale.z),
         ))
         // .insert(RigidBodyPositionSync::Interpolated { prev_pos: None })
@@ -166,8 +171,8 @@ pub fn spawn_character(
         })
         .insert(ThirdPerson {
             is_third_person: true,
-            body: body,
-            head: head,
+            body,
+            head,
         })
         .insert(AtmosphereCamera::default())
         .insert((LookDirection::default(), CameraTag))
@@ -201,7 +206,7 @@ fn toggle_third_person(
     mut models: Query<&mut Visibility>,
 ) {
     // 如果是不能控制状态禁止控制
-    if (!controller_flag.flag) {
+    if !controller_flag.flag {
         return;
     }
     if keyboard_input.just_pressed(KeyCode::T) {
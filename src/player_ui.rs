@@ -1,13 +1,9 @@
-use crate::{
-    classes::*,
-    staff::{Staff, StaffInfoStroge},
-    voxel::{Grass, VoxelMaterial},
-};
+use crate::{classes::*, staff::Staff};
 use bevy::{
     input::mouse::MouseWheel,
     prelude::{
-        App, AssetServer, Changed, Color, Commands, Component, EventReader, Events, Input, KeyCode,
-        Plugin, Query, Res, ResMut, Resource, Startup, Update,
+        App, AssetServer, Changed, Color, Commands, Component, EventReader, Input, KeyCode, Plugin,
+        Query, Res, ResMut, Resource, Startup, Update,
     },
     ui::{BackgroundColor, BorderColor, Interaction, Node, UiImage},
 };
@@ -109,7 +105,7 @@ fn active_system(
     mut query: Query<(&mut Node, &mut BorderColor, &Toolbar)>,
     active_toolbar: Res<ActiveToolbar>,
 ) {
-    for (mut node, mut color, tool_bar) in &mut query {
+    for (_node, mut color, tool_bar) in &mut query {
         if tool_bar.index == active_toolbar.index {
             *color = Color::RED.into();
         } else {
@@ -124,7 +120,7 @@ fn choose_by_key(
     mut active_toolbar: ResMut<ActiveToolbar>,
 ) {
     // 如果是不能控制状态禁止控制
-    if (!controller_flag.flag) {
+    if !controller_flag.flag {
         return;
     }
     add_keyboard_toolbar!(KeyCode::Key1, 0, keyboard_input, active_toolbar);
@@ -152,6 +148,7 @@ fn choose_by_key(
 }
 
 // FIXME: 使用滚轮时长会导致误触！
+#[allow(dead_code)]
 fn choose_by_wheel(
     mut active_toolbar: ResMut<ActiveToolbar>,
     mut mouse_wheel_events: EventReader<MouseWheel>,
@@ -178,11 +175,8 @@ fn choose_toolbar(
     mut active_toolbar: ResMut<ActiveToolbar>,
 ) {
     for (toolbar, inter) in &ui_entities {
-        match (toolbar, inter) {
-            (Toolbar { index }, Interaction::Pressed) => {
-                active_toolbar.index = index.clone();
-            }
-            _ => {}
+        if let (Toolbar { index }, Interaction::Pressed) = (toolbar, inter) {
+            active_toolbar.index = *index;
         }
     }
 }
@@ -196,7 +190,7 @@ pub fn update_hand_holder(
     mut hand_holder: ResMut<HandHolder>,
 ) {
     for tool_bar in &query {
-        if (tool_bar.index == active_toolbar.index) {
+        if tool_bar.index == active_toolbar.index {
             hand_holder.0 = tool_bar.staff.clone();
         }
     }
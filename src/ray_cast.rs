@@ -1,36 +1,17 @@
 use bevy::{
-    pbr::wireframe::Wireframe,
     prelude::{
-        shape::Cube, AlphaMode, Assets, Color, Commands, Component, Gizmos, GlobalTransform, Mesh,
-        PbrBundle, Plugin, Query, Res, ResMut, Resource, StandardMaterial, Startup, Transform,
-        Update, Vec3, Visibility, With, Without,
+        AlphaMode, Assets, Color, Commands, Component, Gizmos, GlobalTransform, Mesh, PbrBundle,
+        Plugin, Query, Res, ResMut, Resource, StandardMaterial, Startup, Transform, Update, Vec3,
+        Visibility, With, Without,
     },
     render::render_resource::PrimitiveTopology,
 };
-use bevy_egui::egui::color_picker::Alpha;
 use bevy_rapier3d::prelude::{QueryFilter, RapierContext};
 use controller::controller::CameraTag;
 
-use crate::{
-    palyer::{PlayerController, PlayerStorge},
-    player_controller::PlayerMe,
-};
-
-fn get_pos_chunk_center(vec3: Vec3, normal: Vec3) -> Vec3 {
-    // 应该是命中点所在的面的中点
-    let mid_pos = Vec3::new(
-        calc_point(vec3.x, normal.x),
-        calc_point(vec3.y, normal.y),
-        calc_point(vec3.z, normal.z),
-    );
-    mid_pos - (normal * 0.5)
-}
-
-fn calc_point(x: f32, normal: f32) -> f32 {
-    // 这里 使用四舍五入进行计算
-    ((x * 1000.0).round() / 1000.0).floor() + 0.5 * (if normal == 0.0 { 1.0 } else { 0.0 })
-}
+use crate::{coordinate::VoxelPos, palyer::PlayerStorge};
 
+#[allow(clippy::type_complexity)]
 pub fn touth_mesh_ray_cast(
     query: Query<&GlobalTransform, With<CameraTag>>,
     rapier_context: Res<RapierContext>,
@@ -43,7 +24,7 @@ pub fn touth_mesh_ray_cast(
     mut gizmos: Gizmos,
     // mut query_visibility: Query<&mut Visibility, With<HelpCube>>,
 ) {
-    let Ok((mut chue_pos,mut visibility)) = query_help_cube.get_single_mut() else{
+    let Ok((mut chue_pos, mut visibility)) = query_help_cube.get_single_mut() else {
         println!("not found CameraTag.");
         return;
     };
@@ -52,7 +33,8 @@ pub fn touth_mesh_ray_cast(
     //  这里需要知道当前相机的位置
     let Ok(tfr) = query.get_single() else {
         println!("not found CameraTag");
-        return;};
+        return;
+    };
     let ray_pos = tfr.translation();
     let ray_dir = tfr.forward();
     // println!("ray_pos: {:?}", ray_pos);
@@ -68,7 +50,7 @@ pub fn touth_mesh_ray_cast(
 
     let hit = rapier_context.cast_ray_and_get_normal(ray_pos, ray_dir, max_toi, solid, filter);
     match hit {
-        Some((entity, intersect)) => {
+        Some((_entity, intersect)) => {
             // println!("物体检查了");
             // 这里物体没有移动的情况下 可以不处理
             // 选择了物体
@@ -77,26 +59,19 @@ pub fn touth_mesh_ray_cast(
             // 显示碰撞到的法向量
             gizmos.ray(hit_point, normal, Color::RED);
             gizmos.circle(hit_point, normal, 0.05, Color::BLACK);
-            let center_point = get_pos_chunk_center(hit_point, normal);
-            // println!("{:?} | {:?} = {:?}", hit_point, normal, center_point);
-            // println!("{:?}", center_point);
-            let out_center_point = get_pos_chunk_center(hit_point, -normal);
-
-            // if let Some(old_center) = choose_cube.center {
-            //     if old_center.distance(center_point) <= 0.0 {
-            //         // println!("物体没有被移动");
-            //         return;
-            //     }
-            // }
+            let center_voxel = VoxelPos::from_hit(hit_point, normal);
+            // println!("{:?} | {:?} = {:?}", hit_point, normal, center_voxel);
+            let out_center_voxel = VoxelPos::from_hit(hit_point, -normal);
+
             // 设置可见
             *visibility = Visibility::Visible;
             // 设置位置
 
-            *chue_pos = Transform::from_translation(center_point);
+            *chue_pos = Transform::from_translation(center_voxel.center().0);
             // 设置选中点
             choose_cube.choose_on = Some(hit_point);
-            choose_cube.center = Some(center_point);
-            choose_cube.out_center = Some(out_center_point);
+            choose_cube.center = Some(center_voxel);
+            choose_cube.out_center = Some(out_center_voxel);
             // println!("normal {:?}", normal);
         }
         None => {
@@ -119,9 +94,9 @@ pub struct ChooseCube {
     // 选中的点
     pub choose_on: Option<Vec3>,
     // 选择中的点对应的方块
-    pub center: Option<Vec3>,
+    pub center: Option<VoxelPos>,
     // 选中点 法向量对面的方块
-    pub out_center: Option<Vec3>,
+    pub out_center: Option<VoxelPos>,
 }
 
 impl ChooseCube {
@@ -205,7 +180,7 @@ fn create_cube_wireframe(size: f32) -> Mesh {
     let mut mesh = Mesh::new(PrimitiveTopology::LineList);
     mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
     mesh.set_indices(Some(bevy::render::mesh::Indices::U32(indices)));
-    return mesh.into();
+    mesh
 }
 
 #[test]
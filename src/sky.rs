@@ -1,5 +1,4 @@
 use bevy::{
-    pbr::CascadeShadowConfigBuilder,
     prelude::{
         Commands, Component, DirectionalLight, DirectionalLightBundle, Plugin, Quat, Query, Res,
         ResMut, Resource, Startup, Transform, Update, Vec3, With,
@@ -29,7 +28,7 @@ fn daylight_cycle(
     timer.0.tick(time.delta());
 
     if timer.0.finished() {
-        let t = time.elapsed_seconds_wrapped() as f32 / 50.0;
+        let t = time.elapsed_seconds_wrapped() / 50.0;
         atmosphere.sun_position = Vec3::new(0., t.sin(), t.cos());
 
         if let Some((mut light_trans, mut directional)) = query.single_mut().into() {
@@ -9,6 +9,7 @@ pub struct Staff {
     // 物品id
     pub id: usize,
     // 物品名称
+    #[allow(dead_code)]
     pub name: String,
     // 物品图示
     pub icon: Handle<Image>,
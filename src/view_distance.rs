@@ -0,0 +1,130 @@
+// 视野距离 可以在设置文件和控制台中修改
+use std::io::Write;
+
+use bevy::prelude::{warn, DetectChanges, IVec3, Res, ResMut, Resource};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk::{generate_offset_resoure, in_cylinder, NeighbourOffest},
+    clip_spheres::ClipSpheres,
+    CHUNK_SIZE, VIEW_RADIUS,
+};
+
+pub const SETTINGS_FILE: &str = "settings.ron";
+// 视野的范围 太小时看不到脚下 太大时加载不过来
+pub const MIN_VIEW_RADIUS: f32 = (CHUNK_SIZE * 2) as f32;
+pub const MAX_VIEW_RADIUS: f32 = (CHUNK_SIZE * 16) as f32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameSettings {
+    // 水平的视野半径
+    pub view_radius: f32,
+    // 上下的视野距离
+    #[serde(default = "default_view_height")]
+    pub view_height: f32,
+}
+
+fn default_view_height() -> f32 {
+    VIEW_RADIUS
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            view_radius: VIEW_RADIUS,
+            view_height: default_view_height(),
+        }
+    }
+}
+
+impl GameSettings {
+    // 没有设置文件时使用默认值
+    pub fn read_file(path: &str) -> Self {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Self::default(),
+        };
+        match ron::de::from_reader(file) {
+            Ok(settings) => settings,
+            Err(e) => {
+                warn!("wrong, to read settings [{}]: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn write_file(&self, path: &str) -> std::io::Result<()> {
+        let data = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(std::io::Error::other)?;
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(data.as_bytes())
+    }
+}
+
+// 加载的范围是圆柱形 水平使用 radius 上下使用 height
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct ViewDistance {
+    pub radius: f32,
+    pub height: f32,
+}
+
+impl ViewDistance {
+    pub fn new(radius: f32, height: f32) -> Self {
+        Self {
+            radius: radius.clamp(MIN_VIEW_RADIUS, MAX_VIEW_RADIUS),
+            height: height.clamp(MIN_VIEW_RADIUS, MAX_VIEW_RADIUS),
+        }
+    }
+
+    pub fn from_settings(settings: &GameSettings) -> Self {
+        Self::new(settings.view_radius, settings.view_height)
+    }
+
+    pub fn chunk_radius(&self) -> f32 {
+        self.radius / CHUNK_SIZE as f32
+    }
+
+    pub fn chunk_height(&self) -> i32 {
+        self.height as i32 / CHUNK_SIZE
+    }
+
+    // 相对于中心 chunk 的偏移是否在视野内 extra 是额外的 chunk 个数
+    pub fn contains(&self, offset: IVec3, extra: i32) -> bool {
+        in_cylinder(
+            offset,
+            self.chunk_radius() + extra as f32,
+            self.chunk_height() + extra,
+        )
+    }
+}
+
+// 视野改变后重新生成偏移 超出范围的 chunk 和 mesh 由卸载系统清理
+pub fn apply_view_distance_system(
+    view_distance: Res<ViewDistance>,
+    mut neighbour_offest: ResMut<NeighbourOffest>,
+    mut clip_spheres: ResMut<ClipSpheres>,
+) {
+    if !view_distance.is_changed() {
+        return;
+    }
+    *neighbour_offest = generate_offset_resoure(view_distance.radius, view_distance.height);
+    clip_spheres.old_sphere.radius = view_distance.radius;
+    clip_spheres.new_sphere.radius = view_distance.radius;
+}
+
+#[test]
+fn test_view_distance() {
+    assert_eq!(ViewDistance::new(1.0, 64.0).radius, MIN_VIEW_RADIUS);
+    assert_eq!(ViewDistance::new(10000.0, 64.0).radius, MAX_VIEW_RADIUS);
+    // 老的设置文件没有高度
+    let settings: GameSettings = ron::from_str("(view_radius: 64.0)").unwrap();
+    let view_distance = ViewDistance::from_settings(&settings);
+    assert_eq!(view_distance.radius, 64.0);
+    assert_eq!(view_distance.height, VIEW_RADIUS);
+
+    let view_distance = ViewDistance::new(64.0, 32.0);
+    assert!(view_distance.contains(IVec3::new(4, 2, 0), 0));
+    assert!(!view_distance.contains(IVec3::new(3, 0, 3), 0));
+    assert!(!view_distance.contains(IVec3::new(0, 3, 0), 0));
+    assert!(view_distance.contains(IVec3::new(0, 3, 0), 1));
+}
@@ -6,17 +6,18 @@ pub struct Voxel {
     pub id: u8,
 }
 
+#[allow(dead_code)]
 impl Voxel {
-    pub const VoxelIds: [u8; 4] = [1, 2, 3, 4];
+    pub const VOXEL_IDS: [u8; 4] = [1, 2, 3, 4];
 
     pub const EMPTY: Self = Self { id: 0 };
     pub const FILLED: Self = Self { id: 1 };
     // 土壤
-    pub const soil: Self = Self { id: 2 };
+    pub const SOIL: Self = Self { id: 2 };
     // 草坪
-    pub const grass: Self = Self { id: 3 };
+    pub const GRASS: Self = Self { id: 3 };
     // 岩石
-    pub const stone: Self = Self { id: 1 };
+    pub const STONE: Self = Self { id: 1 };
 }
 
 impl MeshVoxel for Voxel {
@@ -33,7 +34,7 @@ impl MergeVoxel for Voxel {
     type MergeValue = u8;
 
     fn merge_value(&self) -> Self::MergeValue {
-        return self.id;
+        self.id
     }
 }
 
@@ -49,7 +50,9 @@ pub trait VoxelMaterial {
 #[macro_export]
 macro_rules! voxel_material {
     ($types: ident,$ch_name: ident,$id: expr) => {
+        #[allow(dead_code)]
         pub struct $types;
+        #[allow(dead_code)]
         impl $types {
             pub const NAME: &'static str = stringify!($types);
             pub const CN_NAME: &'static str = stringify!($ch_name);
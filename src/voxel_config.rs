@@ -51,7 +51,6 @@ use bevy_inspector_egui::quick::ResourceInspectorPlugin;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Resource, InspectorOptions, Default, Reflect)]
 #[reflect(Resource, InspectorOptions)]
-
 pub struct MaterailConfiguration {
     // 体素类型列表
     pub voxels: HashMap<u8, VoxelTypeConfig>,
@@ -103,8 +102,8 @@ impl MaterailConfiguration {
                 let file_path = entry.path();
                 // 在这里处理文件，例如打印文件路径
 
-                let path = String::from(file_path.to_str().unwrap().replace("assets/", ""));
-                if (self.files.contains(&path)) {
+                let path = file_path.to_str().unwrap().replace("assets/", "");
+                if self.files.contains(&path) {
                     self.files.push(path);
                     println!("* 文件路径: {}", file_path.display());
                 } else {
@@ -130,7 +129,7 @@ impl MaterailConfiguration {
             Ok(file) => {
                 // 如果成功取配置
                 let res: Self = ron::de::from_reader(file).unwrap();
-                let mut new_self = res.load_all_voxels();
+                let new_self = res.load_all_voxels();
                 // new_self = self.load_pic_files(String::from("assets/textures"));
                 Ok(new_self)
             }
@@ -151,15 +150,13 @@ impl MaterailConfiguration {
 
     // 通过面 和 体素类型获取 图片的索引
     pub fn find_volex_index(self, normal: u8, volex_type: &u8) -> u32 {
-        return match self.voxels.get(volex_type) {
-            Some(config) => {
-                return match config.normal.get(&normal) {
-                    Some(vconfig) => vconfig.index,
-                    None => config.default.index,
-                };
-            }
+        match self.voxels.get(volex_type) {
+            Some(config) => match config.normal.get(&normal) {
+                Some(vconfig) => vconfig.index,
+                None => config.default.index,
+            },
             None => 0,
-        };
+        }
     }
 }
 
@@ -180,7 +177,7 @@ impl Plugin for VoxelMaterialToolPulgin {
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<BindlessMaterial>>,
+    materials: ResMut<Assets<BindlessMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
     let config = MaterailConfiguration::new()
@@ -238,22 +235,17 @@ fn setup(
 
     let mut data = Vec::with_capacity(num_vertices);
 
-    for (block_face_normal_index, (group, face)) in buffer
-        .quads
-        .groups
-        .as_ref()
-        .into_iter()
-        .zip(faces.into_iter())
-        .enumerate()
+    for (block_face_normal_index, (group, face)) in
+        buffer.quads.groups.as_ref().iter().zip(faces).enumerate()
     {
-        for quad in group.into_iter() {
+        for quad in group.iter() {
             indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
-            positions.extend_from_slice(&face.quad_mesh_positions(&quad, 1.0));
+            positions.extend_from_slice(&face.quad_mesh_positions(quad, 1.0));
             normals.extend_from_slice(&face.quad_mesh_normals());
             tex_coords.extend_from_slice(&face.tex_coords(
                 RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
                 true,
-                &quad,
+                quad,
             ));
 
             // 计算出 data
@@ -0,0 +1,442 @@
+// 按世界坐标读写方块 所有修改地形的功能都通过这里
+// 负责找到 chunk 标记需要保存的数据 并安排重新生成 mesh
+use std::collections::HashSet;
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::{EventWriter, IVec3, ResMut, Resource, Vec3},
+};
+
+use crate::{
+    chunk::{ChunkKey, NEIGHBOUR_SIDES},
+    chunk_command::boundary_neighbours,
+    chunk_events::{VoxelChangeCause, VoxelChanged},
+    chunk_generator::ChunkMap,
+    coordinate::{LocalPos, VoxelPos, WorldPos},
+    voxel::{BasicStone, Voxel, VoxelMaterial, Water},
+    SmallKeyHashMap,
+};
+
+// 射线最远检测的距离 超出已加载范围的部分都是空气
+#[allow(dead_code)]
+pub const MAX_RAYCAST_DISTANCE: f32 = 512.0;
+
+// 等待重新生成 mesh 的 section 每帧统一处理
+#[derive(Debug, Default, Resource)]
+pub struct RemeshQueue {
+    pub keys: HashSet<ChunkKey>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoxelEditError {
+    // 所在的 chunk 还没有加载
+    NotLoaded,
+    // 基岩无法破坏
+    Bedrock,
+}
+
+// 射线命中的方块
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct VoxelHit {
+    pub pos: VoxelPos,
+    pub voxel: Voxel,
+    // 命中面的法向量 射线从内部出发时是 0
+    pub normal: IVec3,
+    pub distance: f32,
+}
+
+#[allow(dead_code)]
+impl VoxelHit {
+    // 在命中的面上放置方块的位置
+    pub fn place_pos(&self) -> VoxelPos {
+        VoxelPos(self.pos.0 + self.normal)
+    }
+}
+
+#[derive(SystemParam)]
+pub struct VoxelWorld<'w> {
+    chunk_map: ResMut<'w, ChunkMap>,
+    remesh_queue: ResMut<'w, RemeshQueue>,
+    voxel_changed: EventWriter<'w, VoxelChanged>,
+}
+
+impl<'w> VoxelWorld<'w> {
+    // chunk 没有加载时返回 None
+    #[allow(dead_code)]
+    pub fn get_voxel(&self, pos: VoxelPos) -> Option<Voxel> {
+        let (chunk_key, local) = pos.split();
+        self.chunk_map
+            .get(chunk_key)
+            .map(|voxels| voxels[local.index()])
+    }
+
+    // 修改成功时返回原来的方块 并发送 VoxelChanged
+    pub fn set_voxel(
+        &mut self,
+        pos: VoxelPos,
+        voxel: Voxel,
+        cause: VoxelChangeCause,
+    ) -> Result<Voxel, VoxelEditError> {
+        self.apply_chunk_edits(pos.chunk_key(), &[(pos, voxel)], cause)
+            .pop()
+            .unwrap()
+    }
+
+    // 批量修改 同一个位置只保留最后一次修改 再按 chunk 分组处理
+    // 按每个位置和 chunk 第一次出现的顺序修改 事件的顺序每次都一样
+    // 返回被拒绝的修改 失败的跳过不影响其他的
+    pub fn set_many(
+        &mut self,
+        edits: impl IntoIterator<Item = (VoxelPos, Voxel)>,
+        cause: VoxelChangeCause,
+    ) -> Vec<(VoxelPos, VoxelEditError)> {
+        let mut latest: Vec<(VoxelPos, Voxel)> = Vec::new();
+        let mut latest_index: SmallKeyHashMap<VoxelPos, usize> = SmallKeyHashMap::new();
+        for (pos, voxel) in edits {
+            match latest_index.get(&pos) {
+                Some(&index) => latest[index].1 = voxel,
+                None => {
+                    latest_index.insert(pos, latest.len());
+                    latest.push((pos, voxel));
+                }
+            }
+        }
+        let mut by_chunk: Vec<(ChunkKey, Vec<(VoxelPos, Voxel)>)> = Vec::new();
+        let mut chunk_index: SmallKeyHashMap<ChunkKey, usize> = SmallKeyHashMap::new();
+        for (pos, voxel) in latest {
+            let chunk_key = pos.chunk_key();
+            let index = *chunk_index.entry(chunk_key).or_insert_with(|| {
+                by_chunk.push((chunk_key, Vec::new()));
+                by_chunk.len() - 1
+            });
+            by_chunk[index].1.push((pos, voxel));
+        }
+        let mut rejected = Vec::new();
+        for (chunk_key, chunk_edits) in by_chunk {
+            let results = self.apply_chunk_edits(chunk_key, &chunk_edits, cause);
+            for (&(pos, _), result) in chunk_edits.iter().zip(results) {
+                if let Err(e) = result {
+                    rejected.push((pos, e));
+                }
+            }
+        }
+        rejected
+    }
+
+    // 修改同一个 chunk 里的方块 数据只查找一次 返回每个修改的结果
+    fn apply_chunk_edits(
+        &mut self,
+        chunk_key: ChunkKey,
+        edits: &[(VoxelPos, Voxel)],
+        cause: VoxelChangeCause,
+    ) -> Vec<Result<Voxel, VoxelEditError>> {
+        let Some(voxels) = self.chunk_map.map_data.get_mut(&chunk_key) else {
+            return vec![Err(VoxelEditError::NotLoaded); edits.len()];
+        };
+        let mut results = Vec::with_capacity(edits.len());
+        let mut changed = Vec::new();
+        for &(pos, voxel) in edits {
+            let local = pos.local();
+            let index = local.index();
+            let old = voxels[index];
+            if old.id == BasicStone::ID {
+                results.push(Err(VoxelEditError::Bedrock));
+                continue;
+            }
+            if old.id != voxel.id {
+                voxels[index] = voxel;
+                changed.push((pos, local, old, voxel));
+            }
+            results.push(Ok(old));
+        }
+        if changed.is_empty() {
+            return results;
+        }
+        // 标记修改 由自动保存写回数据库
+        self.chunk_map.mark_dirty(chunk_key);
+        self.chunk_map.touch(chunk_key);
+        // 在边界上时相邻 section 的面也会变化 同一帧里每个 section 只生成一次
+        self.remesh_queue.keys.insert(chunk_key);
+        for (pos, local, old, new) in changed {
+            self.remesh_queue
+                .keys
+                .extend(boundary_neighbours(chunk_key, local));
+            self.voxel_changed.send(VoxelChanged {
+                pos,
+                old,
+                new,
+                cause,
+            });
+        }
+        results
+    }
+
+    // 整个替换 chunk 的数据 比如从数据库重置 不会标记为修改
+    // 已经加载的 chunk 每个变化的方块都发送 VoxelChanged
+    pub fn replace_chunk(&mut self, chunk_key: ChunkKey, voxels: Vec<Voxel>) {
+        if let Some(old_voxels) = self.chunk_map.get(chunk_key) {
+            for (index, (old, new)) in old_voxels.iter().zip(voxels.iter()).enumerate() {
+                if old.id != new.id {
+                    self.voxel_changed.send(VoxelChanged {
+                        pos: VoxelPos::from_local(chunk_key, LocalPos::from_index(index)),
+                        old: *old,
+                        new: *new,
+                        cause: VoxelChangeCause::Command,
+                    });
+                }
+            }
+        }
+        self.chunk_map.write_chunk(chunk_key, voxels);
+        self.chunk_map.dirty.remove(&chunk_key);
+        self.remesh_queue.keys.insert(chunk_key);
+        for side in NEIGHBOUR_SIDES {
+            self.remesh_queue.keys.insert(ChunkKey(chunk_key.0 + side));
+        }
+    }
+
+    pub fn request_remesh(&mut self, chunk_key: ChunkKey) {
+        self.remesh_queue.keys.insert(chunk_key);
+    }
+
+    // 遍历 min 到 max 之间(包含两端)已经加载的方块
+    #[allow(dead_code)]
+    pub fn voxels_in_region(
+        &self,
+        min: VoxelPos,
+        max: VoxelPos,
+    ) -> impl Iterator<Item = (VoxelPos, Voxel)> + '_ {
+        let (min, max) = (min.0.min(max.0), min.0.max(max.0));
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| {
+                (min.z..=max.z).filter_map(move |z| {
+                    let pos = VoxelPos(IVec3::new(x, y, z));
+                    self.get_voxel(pos).map(|voxel| (pos, voxel))
+                })
+            })
+        })
+    }
+
+    // 沿着体素网格前进 返回第一个实心的方块 没有加载的 chunk 当作空气
+    #[allow(dead_code)]
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<VoxelHit> {
+        let dir = direction.normalize_or_zero();
+        if dir == Vec3::ZERO || !origin.is_finite() {
+            return None;
+        }
+        // 无穷大或 NaN 的距离也会限制在最大距离内
+        let max_distance = max_distance.clamp(0.0, MAX_RAYCAST_DISTANCE);
+        // 每一步穿过一个格子 步数不会超过三个轴上的格子数之和
+        let max_steps = (max_distance.ceil() as usize + 1) * 3;
+        let mut current = WorldPos(origin).voxel().0;
+        let mut step = IVec3::ZERO;
+        let mut t_max = Vec3::splat(f32::INFINITY);
+        let mut t_delta = Vec3::splat(f32::INFINITY);
+        for axis in 0..3 {
+            if dir[axis] > 0.0 {
+                step[axis] = 1;
+                t_max[axis] = (current[axis] as f32 + 1.0 - origin[axis]) / dir[axis];
+            } else if dir[axis] < 0.0 {
+                step[axis] = -1;
+                t_max[axis] = (current[axis] as f32 - origin[axis]) / dir[axis];
+            } else {
+                continue;
+            }
+            t_delta[axis] = 1.0 / dir[axis].abs();
+        }
+
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.0;
+        for _ in 0..=max_steps {
+            let pos = VoxelPos(current);
+            if let Some(voxel) = self.get_voxel(pos) {
+                if is_solid(voxel) {
+                    return Some(VoxelHit {
+                        pos,
+                        voxel,
+                        normal,
+                        distance,
+                    });
+                }
+            }
+            let axis = if t_max.x < t_max.y {
+                if t_max.x < t_max.z {
+                    0
+                } else {
+                    2
+                }
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+            distance = t_max[axis];
+            if distance > max_distance {
+                return None;
+            }
+            current[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+        None
+    }
+}
+
+// 射线可以选中的方块 水和空气会被穿过
+pub fn is_solid(voxel: Voxel) -> bool {
+    voxel.id != Voxel::EMPTY.id && voxel.id != Water::ID
+}
+
+#[test]
+fn test_voxel_world() {
+    use bevy::{
+        ecs::{event::Events, system::SystemState},
+        prelude::World,
+    };
+
+    let mut world = World::new();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.write_chunk(ChunkKey(IVec3::ZERO), vec![Voxel::EMPTY; 16 * 16 * 16]);
+    world.insert_resource(chunk_map);
+    world.init_resource::<RemeshQueue>();
+    world.init_resource::<Events<VoxelChanged>>();
+    let mut state: SystemState<VoxelWorld> = SystemState::new(&mut world);
+    let mut voxel_world = state.get_mut(&mut world);
+
+    let pos = VoxelPos(IVec3::new(-8, 3, 7));
+    assert_eq!(
+        voxel_world
+            .set_voxel(pos, Voxel::STONE, VoxelChangeCause::Place)
+            .map(|v| v.id),
+        Ok(Voxel::EMPTY.id)
+    );
+    assert_eq!(
+        voxel_world.get_voxel(pos).map(|v| v.id),
+        Some(Voxel::STONE.id)
+    );
+    assert_eq!(
+        voxel_world
+            .set_voxel(
+                VoxelPos(IVec3::new(8, 0, 0)),
+                Voxel::STONE,
+                VoxelChangeCause::Place
+            )
+            .map(|v| v.id),
+        Err(VoxelEditError::NotLoaded)
+    );
+    let bedrock = VoxelPos(IVec3::new(0, -8, 0));
+    voxel_world
+        .set_voxel(bedrock, BasicStone::into_voxel(), VoxelChangeCause::Command)
+        .unwrap();
+    assert_eq!(
+        voxel_world
+            .set_voxel(bedrock, Voxel::EMPTY, VoxelChangeCause::Break)
+            .map(|v| v.id),
+        Err(VoxelEditError::Bedrock)
+    );
+    assert_eq!(
+        voxel_world
+            .voxels_in_region(
+                VoxelPos(IVec3::new(-9, 3, 7)),
+                VoxelPos(IVec3::new(-7, 3, 7))
+            )
+            .filter(|(_, voxel)| is_solid(*voxel))
+            .count(),
+        1
+    );
+
+    // 一批修改中 非法的单独拒绝 同一个位置只保留最后一次
+    let first = VoxelPos(IVec3::new(1, 1, 1));
+    let rejected = voxel_world.set_many(
+        vec![
+            (first, Voxel::STONE),
+            (bedrock, Voxel::EMPTY),
+            (first, Voxel::SOIL),
+            (VoxelPos(IVec3::new(2, 1, 1)), Voxel::STONE),
+            (VoxelPos(IVec3::new(20, 0, 0)), Voxel::STONE),
+        ],
+        VoxelChangeCause::Command,
+    );
+    // 按输入的顺序拒绝
+    assert_eq!(
+        rejected,
+        vec![
+            (bedrock, VoxelEditError::Bedrock),
+            (VoxelPos(IVec3::new(20, 0, 0)), VoxelEditError::NotLoaded)
+        ]
+    );
+    assert_eq!(
+        voxel_world.get_voxel(first).map(|v| v.id),
+        Some(Voxel::SOIL.id)
+    );
+    assert_eq!(
+        voxel_world
+            .get_voxel(VoxelPos(IVec3::new(2, 1, 1)))
+            .map(|v| v.id),
+        Some(Voxel::STONE.id)
+    );
+
+    // 射线从上方打到 (-8, 3, 7) 的顶面
+    let hit = voxel_world
+        .raycast(Vec3::new(-7.5, 6.5, 7.5), Vec3::NEG_Y, 5.0)
+        .unwrap();
+    assert_eq!(hit.pos, pos);
+    assert_eq!(hit.normal, IVec3::Y);
+    assert_eq!(hit.place_pos(), VoxelPos(IVec3::new(-8, 4, 7)));
+    assert!((hit.distance - 2.5).abs() < 1e-5);
+    assert!(voxel_world
+        .raycast(Vec3::new(-7.5, 6.5, 7.5), Vec3::NEG_Y, 2.0)
+        .is_none());
+    // 距离过大时也会结束
+    assert!(voxel_world
+        .raycast(Vec3::new(-7.5, 6.5, 7.5), Vec3::Y, f32::INFINITY)
+        .is_none());
+    assert!(voxel_world
+        .raycast(Vec3::new(-7.5, 6.5, 7.5), Vec3::Y, f32::NAN)
+        .is_none());
+
+    // 只有成功的修改会发送事件
+    let events = world.resource::<Events<VoxelChanged>>();
+    let causes: Vec<VoxelChangeCause> = events
+        .iter_current_update_events()
+        .map(|event| event.cause)
+        .collect();
+    assert_eq!(
+        causes,
+        vec![
+            VoxelChangeCause::Place,
+            VoxelChangeCause::Command,
+            VoxelChangeCause::Command,
+            VoxelChangeCause::Command
+        ]
+    );
+    // 批量修改的事件按输入的顺序发送
+    let positions: Vec<VoxelPos> = events
+        .iter_current_update_events()
+        .skip(2)
+        .map(|event| event.pos)
+        .collect();
+    assert_eq!(positions, vec![first, VoxelPos(IVec3::new(2, 1, 1))]);
+
+    // 在 x 的负边界上 要重新生成 -x 方向的邻居
+    let remesh_queue = world.resource::<RemeshQueue>();
+    assert!(remesh_queue.keys.contains(&ChunkKey(IVec3::ZERO)));
+    assert!(remesh_queue.keys.contains(&ChunkKey(IVec3::NEG_X)));
+
+    // 重置 chunk 时每个变化的方块都会发送事件
+    world.resource_mut::<Events<VoxelChanged>>().update();
+    let mut voxel_world = state.get_mut(&mut world);
+    voxel_world.replace_chunk(ChunkKey(IVec3::ZERO), vec![Voxel::EMPTY; 16 * 16 * 16]);
+    let events = world.resource::<Events<VoxelChanged>>();
+    let mut reset: Vec<VoxelPos> = events
+        .iter_current_update_events()
+        .filter(|event| event.cause == VoxelChangeCause::Command && event.new.id == Voxel::EMPTY.id)
+        .map(|event| event.pos)
+        .collect();
+    reset.sort_by_key(|pos| pos.0.to_array());
+    assert_eq!(
+        reset,
+        vec![pos, bedrock, first, VoxelPos(IVec3::new(2, 1, 1))]
+    );
+}
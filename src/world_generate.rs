@@ -0,0 +1,119 @@
+// 离线预生成世界的工具 不启动游戏窗口 直接把地形写入数据库
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::IVec3;
+
+use crate::{chunk::ChunkKey, map_database::MapDataBase, map_generator::gen_chunk_data_by_seed};
+
+// 以 center 为中心 半径 radius 个 chunk 的圆柱
+pub fn keys_in_radius(center: IVec3, radius: i32, min_y: i32, max_y: i32) -> Vec<ChunkKey> {
+    let mut keys = Vec::new();
+    for x in -radius..=radius {
+        for z in -radius..=radius {
+            if x * x + z * z > radius * radius {
+                continue;
+            }
+            for y in min_y..=max_y {
+                keys.push(ChunkKey(IVec3::new(center.x + x, y, center.z + z)));
+            }
+        }
+    }
+    keys
+}
+
+// [x0, z0] 到 [x1, z1] 的 chunk 范围 包含两端
+pub fn keys_in_box(from: [i32; 2], to: [i32; 2], min_y: i32, max_y: i32) -> Vec<ChunkKey> {
+    let mut keys = Vec::new();
+    for x in from[0].min(to[0])..=from[0].max(to[0]) {
+        for z in from[1].min(to[1])..=from[1].max(to[1]) {
+            for y in min_y..=max_y {
+                keys.push(ChunkKey(IVec3::new(x, y, z)));
+            }
+        }
+    }
+    keys
+}
+
+// 多线程生成 已经保存过的 chunk 不覆盖 避免丢掉玩家的修改
+// 有线程崩溃时返回错误 已经生成的部分照常保存
+pub fn generate_world(db: &MapDataBase, keys: Vec<ChunkKey>, threads: usize) -> Result<(), String> {
+    let total = keys.len();
+    let threads = threads.max(1);
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let skipped = AtomicUsize::new(0);
+    let seed = db.meta.seed;
+    let start = Instant::now();
+    println!("开始生成 {} 个chunk 线程数 {}", total, threads);
+
+    let panicked = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= total {
+                        break;
+                    }
+                    let key = keys[i];
+                    if db.contains_chunk_key(key) {
+                        skipped.fetch_add(1, Ordering::Relaxed);
+                    } else {
+                        if let Err(e) =
+                            db.update_by_chunk_key(key, &gen_chunk_data_by_seed(seed, key))
+                        {
+                            eprintln!("保存chunk {:?} 失败: {}", key.0, e);
+                        }
+                    }
+                    done.fetch_add(1, Ordering::Relaxed);
+                })
+            })
+            .collect();
+
+        // 进度 线程全部结束后就不再等待 崩溃的线程不会再增加进度
+        let mut last_done = 0;
+        let mut last_time = Instant::now();
+        while done.load(Ordering::Relaxed) < total
+            && !handles.iter().all(|handle| handle.is_finished())
+        {
+            thread::sleep(Duration::from_secs(1));
+            let now_done = done.load(Ordering::Relaxed);
+            let speed = (now_done - last_done) as f32 / last_time.elapsed().as_secs_f32();
+            println!(
+                "进度 {}/{} ({:.1}%) {:.0} chunk/s",
+                now_done,
+                total,
+                now_done as f32 * 100. / total.max(1) as f32,
+                speed
+            );
+            last_done = now_done;
+            last_time = Instant::now();
+        }
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().err())
+            .count()
+    });
+
+    db.flush();
+    if panicked > 0 {
+        return Err(format!(
+            "{} 个生成线程崩溃 只完成了 {}/{} 个chunk",
+            panicked,
+            done.load(Ordering::Relaxed),
+            total
+        ));
+    }
+    let elapsed = start.elapsed().as_secs_f32();
+    println!(
+        "生成结束 共 {} 个chunk 跳过已存在 {} 个 用时 {:.1}s 平均 {:.0} chunk/s",
+        total,
+        skipped.load(Ordering::Relaxed),
+        elapsed,
+        total as f32 / elapsed.max(0.001)
+    );
+    Ok(())
+}
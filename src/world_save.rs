@@ -0,0 +1,158 @@
+// 自动保存修改过的 chunk 退出时同步保存
+// 所有写入都经过这里 同一时间只有一个后台任务 保证同一个 chunk 的新数据最后写入
+use std::{collections::HashSet, time::Duration};
+
+use bevy::{
+    app::AppExit,
+    prelude::{warn, EventReader, IntoSystemConfigs, Last, Plugin, Res, ResMut, Resource, Update},
+    tasks::{AsyncComputeTaskPool, Task},
+    time::{Time, Timer, TimerMode},
+};
+
+use crate::{
+    chunk::ChunkKey,
+    chunk_generator::{chunk_unload_system, ChunkMap},
+    map_database::MapDataBase,
+    voxel::Voxel,
+    SmallKeyHashMap,
+};
+
+#[derive(Debug, Resource, Clone, Copy)]
+pub struct AutoSaveConfig {
+    // 自动保存的间隔 秒
+    pub interval: f32,
+}
+
+impl Default for AutoSaveConfig {
+    fn default() -> Self {
+        Self { interval: 5.0 }
+    }
+}
+
+#[derive(Resource)]
+pub struct AutoSaveState {
+    pub timer: Timer,
+    // 返回没有保存成功的 chunk
+    #[allow(clippy::type_complexity)]
+    pub tasks: Vec<Task<Vec<(ChunkKey, Vec<Voxel>)>>>,
+    // 已经不在 ChunkMap 中 等待写入的数据 卸载时的修改和保存失败的
+    pub pending: SmallKeyHashMap<ChunkKey, Vec<Voxel>>,
+    // 后台任务正在写入的 chunk 写完之前不能从数据库读取
+    pub saving: HashSet<ChunkKey>,
+}
+
+impl AutoSaveState {
+    fn retry_failed(&mut self, failed: Vec<(ChunkKey, Vec<Voxel>)>) {
+        for (chunk_key, voxels) in failed {
+            // 已经有更新的数据在等待时 不用旧的覆盖
+            self.pending.entry(chunk_key).or_insert(voxels);
+        }
+    }
+
+    // 等待后台保存结束 之后再修改数据库不会被旧的数据覆盖
+    pub fn wait_for_saves(&mut self) {
+        for task in std::mem::take(&mut self.tasks) {
+            let failed = futures_lite::future::block_on(task);
+            self.retry_failed(failed);
+        }
+        self.saving.clear();
+    }
+
+    // 卸载时还没有保存的修改 比之前等待的数据都新
+    pub fn queue_unloaded(&mut self, chunk_key: ChunkKey, voxels: Vec<Voxel>) {
+        self.pending.insert(chunk_key, voxels);
+    }
+
+    // 需要写入的全部数据 取出后清除标记 重试的在前面 之后的修改会覆盖它
+    fn take_chunks(&mut self, chunk_map: &mut ChunkMap) -> Vec<(ChunkKey, Vec<Voxel>)> {
+        let mut chunks: Vec<(ChunkKey, Vec<Voxel>)> = self.pending.drain().collect();
+        chunks.extend(chunk_map.take_dirty());
+        chunks
+    }
+}
+
+pub fn autosave_system(
+    time: Res<Time>,
+    config: Res<AutoSaveConfig>,
+    mut state: ResMut<AutoSaveState>,
+    mut chunk_map: ResMut<ChunkMap>,
+    db: Res<MapDataBase>,
+) {
+    // 完成的保存任务 失败的下次重试
+    let mut failed = Vec::new();
+    state.tasks.retain_mut(|task| {
+        match futures_lite::future::block_on(futures_lite::future::poll_once(task)) {
+            Some(result) => {
+                failed.extend(result);
+                false
+            }
+            None => true,
+        }
+    });
+    if !failed.is_empty() {
+        warn!("{} 个chunk保存失败 稍后重试", failed.len());
+        state.retry_failed(failed);
+    }
+    if state.tasks.is_empty() {
+        state.saving.clear();
+    }
+
+    let interval = Duration::from_secs_f32(config.interval.max(0.1));
+    if state.timer.duration() != interval {
+        state.timer.set_duration(interval);
+    }
+    if !state.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    // 上一次还没有写完时不开始新的 避免同一个 chunk 的新旧数据写入顺序颠倒
+    if !state.tasks.is_empty() || (chunk_map.dirty.is_empty() && state.pending.is_empty()) {
+        return;
+    }
+    let chunks = state.take_chunks(chunk_map.as_mut());
+    state.saving = chunks.iter().map(|(chunk_key, _)| *chunk_key).collect();
+    let db = db.clone();
+    let pool = AsyncComputeTaskPool::get();
+    state
+        .tasks
+        .push(pool.spawn(async move { db.save_chunks(chunks) }));
+}
+
+// 退出前等待后台保存结束 然后保存剩下的修改
+pub fn save_on_exit_system(
+    mut exit: EventReader<AppExit>,
+    mut state: ResMut<AutoSaveState>,
+    mut chunk_map: ResMut<ChunkMap>,
+    db: Res<MapDataBase>,
+) {
+    if exit.iter().next().is_none() {
+        return;
+    }
+    state.wait_for_saves();
+    let chunks = state.take_chunks(chunk_map.as_mut());
+    let total = chunks.len();
+    let failed = db.save_chunks(chunks);
+    if !failed.is_empty() {
+        warn!("退出前有 {} 个chunk保存失败", failed.len());
+    }
+    println!("退出前保存了 {} 个chunk", total - failed.len());
+}
+
+pub struct WorldSavePlugin;
+
+impl Plugin for WorldSavePlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<AutoSaveConfig>()
+            .insert_resource(AutoSaveState {
+                timer: Timer::from_seconds(
+                    AutoSaveConfig::default().interval,
+                    TimerMode::Repeating,
+                ),
+                tasks: Vec::new(),
+                pending: SmallKeyHashMap::new(),
+                saving: HashSet::new(),
+            })
+            .add_systems(Update, autosave_system)
+            // 卸载的修改也要在退出前保存
+            .add_systems(Last, save_on_exit_system.after(chunk_unload_system));
+    }
+}
@@ -0,0 +1,47 @@
+// 检查世界中保存的全部 chunk 可以把损坏的数据隔离 下次加载时重新生成
+use crate::{chunk_codec::decode_chunk, map_database::MapDataBase, voxel::Voxel, CHUNK_SIZE};
+
+pub fn verify_world(db: &MapDataBase, repair: bool) {
+    let mut total = 0;
+    let mut bad = Vec::new();
+    let mut read_errors = 0;
+    let iter = match db.store.iter() {
+        Ok(iter) => iter,
+        Err(e) => {
+            println!("无法遍历世界数据: {}", e);
+            return;
+        }
+    };
+    for item in iter {
+        match item {
+            Ok((chunk_key, data)) => {
+                total += 1;
+                // 只检查数据结构 差量数据覆盖在空的 chunk 上 不需要重新生成地形
+                let base = || vec![Voxel::EMPTY; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize];
+                if let Err(e) = decode_chunk(&data, base) {
+                    println!("chunk {:?} 损坏: {}", chunk_key.0, e);
+                    bad.push(chunk_key);
+                }
+            }
+            Err(e) => {
+                read_errors += 1;
+                println!("读取失败: {}", e);
+            }
+        }
+    }
+
+    if repair {
+        for chunk_key in bad.iter() {
+            db.quarantine_chunk(*chunk_key);
+            println!("chunk {:?} 已隔离 下次加载时重新生成", chunk_key.0);
+        }
+        db.flush();
+    }
+    println!(
+        "检查结束 共 {} 个chunk 损坏 {} 个{} 读取失败 {} 次",
+        total,
+        bad.len(),
+        if repair { "(已修复)" } else { "" },
+        read_errors
+    );
+}